@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use crate::*;
+
+const DEFAULT_RETENTION_SECONDS: f64 = 35.0 * 24.0 * 60.0 * 60.0;
+
+type DocumentKey = (db_keys::KeyDatatype, db_keys::KeyDatatype);
+
+#[derive(Clone)]
+struct Entry {
+    lsn: u64,
+    timestamp: f64,
+    key: DocumentKey,
+    keys: Value,
+    // None when the write removed the document
+    document: Option<Value>,
+}
+
+struct BackupLog {
+    // the table as of base_time, which is as far back as it can be restored
+    base_time: f64,
+    base: BTreeMap<DocumentKey, Value>,
+    // every write since, in log order
+    entries: VecDeque<Entry>,
+    last_lsn: u64,
+    // lsn the table's backups file holds every entry through, None until the whole log has been written there
+    saved_lsn: Option<u64>,
+    // entry lines in that file, and the newest entry folded into the base, which the file can't follow unless it held it
+    file_entries: usize,
+    folded_lsn: u64,
+}
+
+impl BackupLog {
+    // folds writes that have left the retention window into the base
+    fn trim(&mut self, cutoff: f64) {
+        while let Some(entry) = self.entries.front() && entry.timestamp <= cutoff {
+            let entry = self.entries.pop_front().unwrap();
+            self.folded_lsn = entry.lsn;
+            apply(&mut self.base, entry.key, entry.document);
+        }
+        self.base_time = self.base_time.max(cutoff);
+    }
+
+    fn push(&mut self, keys: [&db_keys::Key; 2], entry: &Value) -> Result<(), errors::DbError> {
+        let keys_json = entry.get("keys").ok_or_else(invalid_state)?;
+        self.entries.push_back(Entry {
+            lsn: entry.get("lsn").and_then(|l| l.as_u64()).ok_or_else(invalid_state)?,
+            timestamp: entry.get("timestamp").and_then(|t| t.as_f64()).ok_or_else(invalid_state)?,
+            key: document_key(keys, keys_json)?,
+            keys: keys_json.clone(),
+            document: entry.get("document").filter(|d| !d.is_null()).cloned(),
+        });
+        Ok(())
+    }
+
+    // the header line of the backups file, and the first part of the state logged with the table
+    fn header(&self) -> (f64, u64, Vec<Value>) {
+        (self.base_time, self.last_lsn, self.base.values().cloned().collect())
+    }
+}
+
+fn entry_to_json(entry: &Entry) -> Value {
+    serde_json::json!({
+        "lsn": entry.lsn,
+        "timestamp": entry.timestamp,
+        "keys": entry.keys,
+        "document": entry.document,
+    })
+}
+
+fn header_to_json((base_time, last_lsn, base): (f64, u64, Vec<Value>)) -> Value {
+    serde_json::json!({
+        "base_time": base_time,
+        "last_lsn": last_lsn,
+        "base": base,
+    })
+}
+
+// what a save writes to a table's backups file: the whole log, or the lines to append to it
+pub struct BackupsFile {
+    pub rewrite: bool,
+    pub contents: Vec<u8>,
+    through: u64,
+    lines: usize,
+}
+
+fn apply(documents: &mut BTreeMap<DocumentKey, Value>, key: DocumentKey, document: Option<Value>) {
+    match document {
+        Some(document) => {documents.insert(key, document);}
+        None => {documents.remove(&key);}
+    }
+}
+
+fn document_key(keys: [&db_keys::Key; 2], document: &Value) -> Result<DocumentKey, errors::DbError> {
+    Ok((db_keys::extract_key(keys[0], document)?, db_keys::extract_key(keys[1], document)?))
+}
+
+fn invalid_state() -> errors::DbError {
+    errors::DbError::BadInput(String::from("backups must hold a valid point in time recovery log"))
+}
+
+// continuous backups of one table: a base image plus every write within the retention window,
+// saved beside the table's files so the window survives restarts
+pub struct Backups {
+    retention_seconds: f64,
+    log: std::sync::Mutex<BackupLog>,
+}
+
+impl Backups {
+    pub fn from_json(info: Option<&Value>) -> Result<Option<Self>, errors::DbError> {
+        match info {
+            None | Some(Value::Null) => {Ok(None)}
+            Some(info) if info.is_object() => {
+                let retention_seconds = match info.get("retention_seconds") {
+                    None | Some(Value::Null) => {DEFAULT_RETENTION_SECONDS}
+                    Some(r) => {
+                        match r.as_f64() {
+                            Some(r) if r > 0.0 => {r}
+                            _ => {return Err(errors::DbError::BadInput(String::from("retention_seconds must be a positive number")));}
+                        }
+                    }
+                };
+                Ok(Some(Self {
+                    retention_seconds,
+                    log: std::sync::Mutex::new(BackupLog {
+                        base_time: ttl::now(),
+                        base: BTreeMap::new(),
+                        entries: VecDeque::new(),
+                        last_lsn: 0,
+                        saved_lsn: None,
+                        file_entries: 0,
+                        folded_lsn: 0,
+                    }),
+                }))
+            }
+            Some(_) => {Err(errors::DbError::BadInput(String::from("point_in_time_recovery must be a valid JSON object")))}
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "retention_seconds": self.retention_seconds,
+        })
+    }
+
+    // backups enabled on a table that already has documents start from them
+    pub fn set_base(&self, keys: [&db_keys::Key; 2], documents: &[Value]) -> Result<(), errors::DbError> {
+        let mut log = self.log.lock().unwrap();
+        for document in documents {
+            log.base.insert(document_key(keys, document)?, document.clone());
+        }
+        Ok(())
+    }
+
+    // the log a state or file holds, everything before entries being in its header; the current one is only
+    // replaced once all of it has loaded
+    fn load(&self, keys: [&db_keys::Key; 2], header: &Value, entries: &[Value]) -> Result<BackupLog, errors::DbError> {
+        let mut log = BackupLog {
+            base_time: header.get("base_time").and_then(|t| t.as_f64()).ok_or_else(invalid_state)?,
+            base: BTreeMap::new(),
+            entries: VecDeque::new(),
+            last_lsn: header.get("last_lsn").and_then(|l| l.as_u64()).ok_or_else(invalid_state)?,
+            saved_lsn: None,
+            file_entries: 0,
+            folded_lsn: 0,
+        };
+        for document in header.get("base").and_then(|b| b.as_array()).ok_or_else(invalid_state)? {
+            log.base.insert(document_key(keys, document)?, document.clone());
+        }
+        for entry in entries {
+            log.push(keys, entry)?;
+        }
+        log.last_lsn = log.last_lsn.max(log.entries.back().map_or(0, |entry| entry.lsn));
+        Ok(log)
+    }
+
+    // the state logged with the table when it is created or rebuilt, which still needs its own file written
+    pub fn load_state(&self, keys: [&db_keys::Key; 2], state: &Value) -> Result<(), errors::DbError> {
+        let log = self.load(keys, state, state.get("entries").and_then(|e| e.as_array()).ok_or_else(invalid_state)?)?;
+        *self.log.lock().unwrap() = log;
+        Ok(())
+    }
+
+    // lines of the table's backups file, a header and then one per entry; a torn last line from a crashed
+    // append is left out, and the file rewritten at the next save
+    pub fn load_file(&self, keys: [&db_keys::Key; 2], lines: &[Value], torn: bool) -> Result<(), errors::DbError> {
+        let (header, entries) = lines.split_first().ok_or_else(invalid_state)?;
+        let mut log = self.load(keys, header, entries)?;
+        log.file_entries = entries.len();
+        log.saved_lsn = if torn {None} else {Some(log.last_lsn)};
+        *self.log.lock().unwrap() = log;
+        Ok(())
+    }
+
+    // cloned under the lock and built after, so writers recording backups don't wait on it
+    pub fn state_to_json(&self) -> Value {
+        let (header, entries) = {
+            let log = self.log.lock().unwrap();
+            (log.header(), log.entries.iter().cloned().collect::<Vec<Entry>>())
+        };
+        let mut output = header_to_json(header);
+        output["entries"] = entries.iter().map(entry_to_json).collect();
+        output
+    }
+
+    // what the next save writes: the entries since the last one, or the whole log when the file doesn't hold
+    // the rest of it or holds more stale lines than a rewrite would take; None when there is nothing new
+    pub fn to_file(&self) -> Option<BackupsFile> {
+        let (header, entries, through) = {
+            let log = self.log.lock().unwrap();
+            let saved = log.saved_lsn.filter(|saved_lsn| log.folded_lsn <= *saved_lsn);
+            match saved {
+                Some(saved_lsn) => {
+                    let start = log.entries.partition_point(|entry| entry.lsn <= saved_lsn);
+                    let stale = log.file_entries.saturating_sub(start);
+                    if stale <= log.entries.len() + log.base.len() {
+                        if log.last_lsn <= saved_lsn {
+                            return None;
+                        }
+                        (None, log.entries.range(start..).cloned().collect::<Vec<Entry>>(), log.last_lsn)
+                    } else {
+                        (Some(log.header()), log.entries.iter().cloned().collect(), log.last_lsn)
+                    }
+                }
+                None => {(Some(log.header()), log.entries.iter().cloned().collect(), log.last_lsn)}
+            }
+        };
+        let mut contents = Vec::new();
+        let rewrite = header.is_some();
+        for line in header.map(header_to_json).into_iter().chain(entries.iter().map(entry_to_json)) {
+            contents.extend(serde_json::to_vec(&line).unwrap());
+            contents.push(b'\n');
+        }
+        Some(BackupsFile {rewrite, contents, through, lines: entries.len()})
+    }
+
+    // once a save has written what to_file returned
+    pub fn saved(&self, file: &BackupsFile) {
+        let mut log = self.log.lock().unwrap();
+        log.file_entries = if file.rewrite {file.lines} else {log.file_entries + file.lines};
+        log.saved_lsn = Some(file.through);
+    }
+
+    // a failed write may have left part of a line behind, so the next save starts the file over
+    pub fn save_failed(&self) {
+        self.log.lock().unwrap().saved_lsn = None;
+    }
+
+    // called in log order with every write of one record, a transaction logging several at once;
+    // records replayed on startup that the saved log already holds are skipped
+    pub fn record(&self, lsn: u64, timestamp: f64, writes: Vec<(DocumentKey, Value, Option<Value>)>) {
+        let mut log = self.log.lock().unwrap();
+        if lsn <= log.last_lsn {
+            return;
+        }
+        log.last_lsn = lsn;
+        for (key, keys, document) in writes {
+            log.entries.push_back(Entry {lsn, timestamp, key, keys, document});
+        }
+        log.trim(timestamp - self.retention_seconds);
+    }
+
+    pub fn describe(&self) -> Value {
+        let now = ttl::now();
+        let mut log = self.log.lock().unwrap();
+        log.trim(now - self.retention_seconds);
+        serde_json::json!({
+            "retention_seconds": self.retention_seconds,
+            "earliest_restorable_time": log.base_time,
+            "latest_restorable_time": now,
+        })
+    }
+
+    // the table's documents as they were at timestamp
+    pub fn documents_at(&self, timestamp: f64) -> Result<Vec<Value>, errors::DbError> {
+        let now = ttl::now();
+        let mut log = self.log.lock().unwrap();
+        log.trim(now - self.retention_seconds);
+        if timestamp < log.base_time || timestamp > now {
+            return Err(errors::DbError::BadInput(format!(
+                "timestamp must be between {} and {}", log.base_time, now
+            )));
+        }
+        let mut documents = log.base.clone();
+        for entry in log.entries.iter().filter(|entry| entry.timestamp <= timestamp) {
+            apply(&mut documents, entry.key.clone(), entry.document.clone());
+        }
+        Ok(documents.into_values().collect())
+    }
+}
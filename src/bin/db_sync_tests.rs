@@ -18,7 +18,7 @@ async fn main() {
 
     db.create_document(&"students".to_string(), j["doc_obj"].clone()).await.unwrap();
 
-    println!("{}", db.read_db().await.unwrap());
+    println!("{}", db.read_db(serde_json::Value::Null).await.unwrap());
     println!("{}", db.read_table(&"students".to_string()).await.unwrap());
     println!("{}", db.read_document(&"students".to_string(), j["query_obj"].clone()).await.unwrap());
 
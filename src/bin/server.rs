@@ -1,5 +1,7 @@
 use axum::response::IntoResponse;
 
+const TTL_SWEEP_SECONDS: u64 = 5;
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
@@ -36,11 +38,19 @@ fn convert_to_response(result: Result<serde_json::Value, laws::errors::DbError>)
             let status_code = match e {
                 laws::errors::DbError::MissingFields(_) => {axum::http::StatusCode::FAILED_DEPENDENCY}
                 laws::errors::DbError::TableNotFound(_) => {axum::http::StatusCode::NOT_FOUND}
+                laws::errors::DbError::IndexNotFound(_) => {axum::http::StatusCode::NOT_FOUND}
                 laws::errors::DbError::BadInput(_) => {axum::http::StatusCode::BAD_REQUEST}
+                laws::errors::DbError::ConditionFailed(_) => {axum::http::StatusCode::CONFLICT}
+                laws::errors::DbError::TransactionCanceled(_) => {axum::http::StatusCode::CONFLICT}
+                laws::errors::DbError::StorageError(_) => {axum::http::StatusCode::INTERNAL_SERVER_ERROR}
             };
+            let mut body = serde_json::json!({"message": e.to_string()});
+            if let laws::errors::DbError::TransactionCanceled(reasons) = e {
+                body["cancellation_reasons"] = reasons;
+            }
             (
                 status_code,
-                axum::Json::from(serde_json::json!({"message": e.to_string()})),
+                axum::Json::from(body),
                 )
         }
     }
@@ -49,7 +59,25 @@ fn convert_to_response(result: Result<serde_json::Value, laws::errors::DbError>)
 #[tokio::main]
 async fn main() {
 
-    let db = std::sync::Arc::new(laws::database::Database::new("./data").await);
+    let config = match laws::config::Config::from_env() {
+        Ok(config) => {config}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let (autosave_seconds, autosave_mutations) = (config.autosave_seconds, config.autosave_mutations);
+    let sync_period = match config.wal_sync {
+        laws::wal::SyncPolicy::Interval(interval) => {Some(interval)}
+        _ => {None}
+    };
+    let db = match laws::database::Database::with_config("./data", config).await {
+        Ok(db) => {std::sync::Arc::new(db)}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     let fs = std::sync::Arc::new(laws::storage::Storage::new());
 
     let app = axum::Router::new()
@@ -73,11 +101,48 @@ async fn main() {
                 .delete(async || {})
         )
 
+        // persistence status
+        .route(
+            "/admin/save",
+            axum::routing::get(async |db: axum::extract::State<std::sync::Arc<laws::database::Database>>| {
+                convert_to_response(Ok(db.save_status())).into_response()
+            })
+        )
+
         // DB level CRUD
         .route(
             "/db",
-            axum::routing::get(async |db: axum::extract::State<std::sync::Arc<laws::database::Database>>| {
-                convert_to_response(db.read_db().await).into_response()
+            axum::routing::get(async |db: axum::extract::State<std::sync::Arc<laws::database::Database>>, info: Option<axum::Json<serde_json::Value>>| {
+                let info = info.map(|axum::Json(info)| info).unwrap_or(serde_json::Value::Null);
+                convert_to_response(db.read_db(info).await).into_response()
+            })
+        )
+
+        // transactions across tables
+        .route(
+            "/db/transact/write",
+            axum::routing::post(async |db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                convert_to_response(db.transact_write(info).await).into_response()
+            })
+        )
+        .route(
+            "/db/transact/get",
+            axum::routing::get(async |db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                convert_to_response(db.transact_get(info).await).into_response()
+            })
+        )
+
+        // batches of independent document operations
+        .route(
+            "/db/batch/write",
+            axum::routing::post(async |db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                convert_to_response(db.batch_write(info).await).into_response()
+            })
+        )
+        .route(
+            "/db/batch/get",
+            axum::routing::get(async |db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                convert_to_response(db.batch_get(info).await).into_response()
             })
         )
 
@@ -91,11 +156,27 @@ async fn main() {
                    .get(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
                        convert_to_response(db.read_table(&table_name).await).into_response()
                    })
+                   .put(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                       convert_to_response(db.update_table(&table_name, info).await).into_response()
+                   })
                    .delete(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
                        convert_to_response(db.delete_table(&table_name).await).into_response()
                    })
         )
 
+        .route(
+            "/db/table/{table_name}/describe",
+               axum::routing::get(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>| {
+                   convert_to_response(db.describe_table(&table_name).await).into_response()
+               })
+        )
+        .route(
+            "/db/table/{table_name}/restore",
+               axum::routing::post(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                   convert_to_response(db.restore_table(&table_name, info).await).into_response()
+               })
+        )
+
         // document level CRUD
         .route(
             "/db/table/{table_name}/doc",
@@ -112,9 +193,90 @@ async fn main() {
                        convert_to_response(db.delete_document(&table_name, info).await).into_response()
                    })
         )
+        .route(
+            "/db/table/{table_name}/query",
+               axum::routing::get(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                   convert_to_response(db.query(&table_name, info).await).into_response()
+               })
+        )
+        .route(
+            "/db/table/{table_name}/scan",
+               axum::routing::get(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                   convert_to_response(db.scan(&table_name, info).await).into_response()
+               })
+        )
+        .route(
+            "/db/table/{table_name}/index",
+               axum::routing::post(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                   convert_to_response(db.create_index(&table_name, info).await).into_response()
+               })
+        )
+        .route(
+            "/db/table/{table_name}/index/{index_name}",
+               axum::routing::delete(async |axum::extract::Path((table_name, index_name)): axum::extract::Path<(String, String)>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>| {
+                   convert_to_response(db.delete_index(&table_name, &index_name).await).into_response()
+               })
+        )
+        .route(
+            "/db/table/{table_name}/stream",
+               axum::routing::get(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                   convert_to_response(db.get_stream_cursor(&table_name, info).await).into_response()
+               })
+        )
+        .route(
+            "/db/table/{table_name}/stream/records",
+               axum::routing::get(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::Json(info): axum::Json<serde_json::Value>| {
+                   convert_to_response(db.read_stream(&table_name, info).await).into_response()
+               })
+        )
+        .route(
+            "/db/table/{table_name}/subscribe",
+               axum::routing::get(async |axum::extract::Path(table_name): axum::extract::Path<String>, db: axum::extract::State<std::sync::Arc<laws::database::Database>>, axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>| {
+                   match db.subscribe(&table_name, params.get("partition_key").map(|s| s.as_str())).await {
+                       Err(e) => {convert_to_response(Err(e)).into_response()}
+                       Ok(subscription) => {
+                           let events = futures_util::stream::unfold(subscription, async |mut subscription| {
+                               let event = subscription.next().await?;
+                               Some((Ok::<_, std::convert::Infallible>(axum::response::sse::Event::default().data(event.to_string())), subscription))
+                           });
+                           axum::response::sse::Sse::new(events).keep_alive(axum::response::sse::KeepAlive::default()).into_response()
+                       }
+                   }
+               })
+        )
         .with_state(db.clone())
         .with_state(fs.clone());
 
+    // expired documents are already hidden from reads, this only reclaims them
+    let sweeper = db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(TTL_SWEEP_SECONDS));
+        loop {
+            interval.tick().await;
+            sweeper.sweep_expired().await;
+        }
+    });
+
+    // writes that arrived too close together to be fsynced at the time are caught up here
+    if let Some(sync_period) = sync_period {
+        let syncer = db.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(sync_period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = syncer.sync_wal().await {
+                    eprintln!("{}", e);
+                }
+            }
+        });
+    }
+
+    // folds the write-ahead log into the table files so it doesn't grow without bound
+    let autosaver = db.clone();
+    tokio::spawn(async move {
+        autosaver.autosave(autosave_seconds, autosave_mutations).await;
+    });
+
     let listener = tokio::net::TcpListener::bind("[::1]:6969").await.unwrap();
     axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await.unwrap();
 
@@ -0,0 +1,64 @@
+use crate::*;
+
+// what startup does with a table file, snapshot or log it can't read
+pub enum CorruptionPolicy {
+    // move it aside and recover from what is left
+    Quarantine,
+    // leave everything untouched and stop
+    Refuse,
+}
+
+impl CorruptionPolicy {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "quarantine" => {Ok(Self::Quarantine)}
+            "refuse" => {Ok(Self::Refuse)}
+            _ => {Err(String::from("LAWS_ON_CORRUPTION must be quarantine or refuse"))}
+        }
+    }
+}
+
+pub struct Config {
+    pub wal_sync: wal::SyncPolicy,
+    // the write-ahead log is folded into the table files this often, and once this many writes are waiting; 0 turns either off
+    pub autosave_seconds: u64,
+    pub autosave_mutations: u64,
+    pub on_corruption: CorruptionPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            wal_sync: wal::SyncPolicy::Interval(std::time::Duration::from_millis(1000)),
+            autosave_seconds: 60,
+            autosave_mutations: 1000,
+            on_corruption: CorruptionPolicy::Quarantine,
+        }
+    }
+}
+
+fn env_u64(name: &str, default: u64) -> Result<u64, String> {
+    match std::env::var(name) {
+        Err(_) => {Ok(default)}
+        Ok(s) => {s.parse().map_err(|_| format!("{} must be a whole number", name))}
+    }
+}
+
+impl Config {
+    // LAWS_WAL_SYNC is always, never, or the most milliseconds a write may wait to be fsynced
+    pub fn from_env() -> Result<Self, String> {
+        let default = Self::default();
+        Ok(Self {
+            wal_sync: match std::env::var("LAWS_WAL_SYNC") {
+                Err(_) => {default.wal_sync}
+                Ok(s) => {wal::SyncPolicy::parse(&s)?}
+            },
+            autosave_seconds: env_u64("LAWS_AUTOSAVE_SECONDS", default.autosave_seconds)?,
+            autosave_mutations: env_u64("LAWS_AUTOSAVE_MUTATIONS", default.autosave_mutations)?,
+            on_corruption: match std::env::var("LAWS_ON_CORRUPTION") {
+                Err(_) => {default.on_corruption}
+                Ok(s) => {CorruptionPolicy::parse(&s)?}
+            },
+        })
+    }
+}
@@ -1,8 +1,28 @@
 use crate::{*};
 
 pub struct Database {
-    tables: Arc<RwLock<HashMap<String, table::Table >>>,
+    // shared so a save can serialize tables without holding the tables lock
+    tables: Arc<RwLock<HashMap<String, Arc<table::Table>>>>,
     path: String,
+    wal: Arc<wal::Wal>,
+    // held while saving, and by anything that removes table files, so those never interleave
+    saves: Arc<tokio::sync::Mutex<SaveState>>,
+    // kept apart from the save state, so it can be reported while a save is running
+    status: Arc<std::sync::Mutex<SaveStatus>>,
+}
+
+struct SaveStatus {
+    saved_at: Option<f64>,
+    lsn: u64,
+    error: Option<String>,
+}
+
+struct SaveState {
+    // lsn the last save captured; the log only has to keep what comes after the save before it
+    lsn: u64,
+    files: HashMap<String, snapshot::TableFiles>,
+    // tables still come from the single snapshot older versions wrote, until a save has written them all
+    legacy: bool,
 }
 
 impl Clone for Database {
@@ -10,98 +30,187 @@ impl Clone for Database {
         Self {
             tables: self.tables.clone(),
             path: self.path.clone(),
+            wal: self.wal.clone(),
+            saves: self.saves.clone(),
+            status: self.status.clone(),
         }
     }
 }
 
 impl Database {
 
-    async fn load(path: &str) -> Self {
+    // every table is loaded from the newest of its files that can be read, then every logged write its file
+    // doesn't already cover is replayed on top; nothing on disk is overwritten here, unreadable files are only moved aside
+    async fn load(path: &str, config: config::Config) -> Result<Self, errors::DbError> {
         let p = std::path::Path::new(path);
+        let tables_dir = p.join("tables");
+        std::fs::create_dir_all(&tables_dir).map_err(|e| errors::DbError::StorageError(format!("Could not create {}: {}", tables_dir.display(), e)))?;
 
-        // directory existence check
-        match std::fs::exists(p) {
-            Ok(b) => {if !b {return Self::cold_start(path);}}
-            Err(_) => {return Self::cold_start(path);}
-        }
-
-        // file existence check
-        match std::fs::exists(p.join("data.json")) {
-            Ok(b) => {if !b {return Self::cold_start(path);}}
-            Err(_) => {return Self::cold_start(path);}
-        }
-
-        // file opening check
-        let file = std::fs::File::open(p.join("data.json"));
-        if file.is_err() {
-            return Self::cold_start(path);
+        let mut tables = HashMap::new();
+        // lsn each table's file covers; tables without a file fall back to default_lsn
+        let mut lsns = HashMap::new();
+        let mut default_lsn = 0;
+        let mut legacy = false;
+        for file_name in snapshot::LEGACY_FILES {
+            match snapshot::read(&p.join(file_name)).await {
+                Ok(None) => {}
+                Ok(Some((lsn, loaded))) => {
+                    default_lsn = lsn;
+                    legacy = true;
+                    for table in loaded {
+                        lsns.insert(table.table_name.clone(), lsn);
+                        tables.insert(table.table_name.clone(), table);
+                    }
+                    break;
+                }
+                Err(reason) => {snapshot::quarantine(p, file_name, &reason, &config.on_corruption)?;}
+            }
         }
-        let mut file = file.unwrap();
 
-        // file read
-        let mut s = String::new();
-        if file.read_to_string(&mut s).is_err() {
-            return Self::cold_start(path);
+        // table files are always newer than a legacy snapshot that is still around
+        let mut files = HashMap::new();
+        for stem in snapshot::stems(&tables_dir)? {
+            for (i, file_name) in snapshot::table_files(&stem).iter().enumerate() {
+                match snapshot::read(&tables_dir.join(file_name)).await {
+                    Ok(None) => {}
+                    Ok(Some((lsn, loaded))) => {
+                        for table in loaded {
+                            // the fallback's lsn isn't known without reading it, so the next save refreshes it
+                            if i == 0 {
+                                files.insert(table.table_name.clone(), snapshot::TableFiles {saved_lsn: lsn, prev_lsn: 0});
+                            }
+                            lsns.insert(table.table_name.clone(), lsn);
+                            tables.insert(table.table_name.clone(), table);
+                        }
+                        break;
+                    }
+                    Err(reason) => {snapshot::quarantine(&tables_dir, file_name, &reason, &config.on_corruption)?;}
+                }
+            }
+            // a table without its backups file keeps what its table file gave it, which the next save writes out
+            let Some(table) = tables.values().find(|table| snapshot::file_stem(&table.table_name) == stem && table.backups().is_some()) else {
+                continue;
+            };
+            let file_name = snapshot::backups_file(&stem);
+            let loaded = snapshot::read_backups(&tables_dir.join(&file_name))
+                .and_then(|lines| match lines {
+                    None => {Ok(())}
+                    Some((lines, torn)) => {table.load_backups(&lines, torn).map_err(|e| e.to_string())}
+                });
+            if let Err(reason) = loaded {
+                snapshot::quarantine(&tables_dir, &file_name, &reason, &config.on_corruption)?;
+            }
         }
 
-        // JSON-ify check
-        let tables = serde_json::from_str::<Vec<Value>>(s.as_str());
-        if tables.is_err() {
-            return Self::cold_start(path);
+        let log_path = p.join("wal.log");
+        let (records, damaged) = wal::Wal::read(&log_path)?;
+        for file_name in damaged {
+            snapshot::quarantine(p, &file_name, "records past the readable ones could not be parsed", &config.on_corruption)?;
         }
-        let tables: Vec<Value> = tables.unwrap();
-
-        let mut output = HashMap::new();
-        for table in tables {
-            let table = table::Table::new(&table).await;
-            if table.is_err() {
-                return Self::cold_start(path);
+        // records the files already cover are kept too, their fallbacks still need them until the next save
+        let mut next_lsn = default_lsn + 1;
+        for record in &records {
+            let record_lsn = record["lsn"].as_u64().unwrap();
+            next_lsn = next_lsn.max(record_lsn + 1);
+            let covered = |table_name: &str| record_lsn <= lsns.get(table_name).copied().unwrap_or(default_lsn);
+            match replay(&mut tables, record, covered).await {
+                // writes to a table that was dropped before its files were last cleaned up
+                Ok(_) | Err(errors::DbError::TableNotFound(_)) => {}
+                Err(e) => {eprintln!("Skipping write-ahead log record {}: {}", record_lsn, e);}
             }
-            let table = table.unwrap();
-            output.insert(table.table_name.clone(), table);
         }
+        next_lsn = next_lsn.max(lsns.values().max().map_or(0, |lsn| lsn + 1));
 
-        Self {
-            tables: Arc::new(RwLock::new(output)),
+        let wal = Arc::new(wal::Wal::open(&log_path, config.wal_sync, &records, next_lsn)?);
+        let tables = tables.into_iter().map(|(table_name, mut table)| {
+            table.attach_wal(wal.clone());
+            (table_name, Arc::new(table))
+        }).collect();
+        Ok(Self {
+            tables: Arc::new(RwLock::new(tables)),
             path: path.to_string(),
-        }
+            wal,
+            saves: Arc::new(tokio::sync::Mutex::new(SaveState {
+                lsn: default_lsn,
+                files,
+                legacy,
+            })),
+            status: Arc::new(std::sync::Mutex::new(SaveStatus {
+                saved_at: None,
+                lsn: default_lsn,
+                error: None,
+            })),
+        })
     }
 
-    fn cold_start(path: &str) -> Self {
-        let p = std::path::Path::new(path);
-        let _ = std::fs::remove_dir(p);
-        let _ = std::fs::create_dir(p);
-        let _ = std::fs::File::create(p.join("data.json"));
-        Self {
-            tables: Arc::new(RwLock::new(HashMap::new())),
-            path: path.to_string(),
-        }
+    fn tables_dir(&self) -> std::path::PathBuf {
+        std::path::Path::new(self.path.as_str()).join("tables")
     }
 
     pub async fn new(path: &str) -> Self {
-        Self::load(path).await
+        match Self::load(path, config::Config::default()).await {
+            Ok(db) => {db}
+            Err(e) => {panic!("{}", e)}
+        }
     }
 
-    pub async fn read_db(&self) -> Result<Value, errors::DbError> {
+    pub async fn with_config(path: &str, config: config::Config) -> Result<Self, errors::DbError> {
+        Self::load(path, config).await
+    }
+
+    pub async fn read_db(&self, info: Value) -> Result<Value, errors::DbError> {
+        let limit = pagination::limit(&info)?.unwrap_or(usize::MAX);
+        let start = match pagination::start_key(&info)? {
+            None => {None}
+            Some(Value::String(s)) => {Some(s)}
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("Continuation token is not a table listing token")));}
+        };
         let guard = self.tables.read().await;
-        let mut v = Vec::new();
-        v.reserve(guard.keys().len());
-        for db_keys in guard.keys() {
-            v.push(db_keys);
+        let mut names: Vec<&String> = guard.keys().filter(|name| start.as_ref().is_none_or(|s| *name > s)).collect();
+        names.sort();
+        let mut last_evaluated_key = None;
+        if names.len() > limit {
+            names.truncate(limit);
+            last_evaluated_key = names.last().map(|name| pagination::encode_token(&Value::String(name.to_string())));
         }
         Ok(serde_json::json!({
-            "tables": v
+            "tables": names,
+            "last_evaluated_key": last_evaluated_key,
         }))
     }
 
 
     pub async fn create_table(&self, info: Value) -> Result<Value, errors::DbError> {
-        let table = table::Table::new(&info).await?;
+        let mut table = table::Table::new(&info).await?;
         let mut guard = self.tables.write().await;
-        guard.deref_mut().insert(table.table_name.clone(), table);
+        self.wal.append(serde_json::json!({"op": "put_table", "definition": table.to_snapshot().await?}), |lsn| table.mark_changed(lsn)).await?;
+        table.attach_wal(self.wal.clone());
+        guard.deref_mut().insert(table.table_name.clone(), Arc::new(table));
         Ok(NULL_VAL)
     }
 
+    // creates a new table from another one's backups, as it was at the requested time
+    pub async fn restore_table(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
+        validation::check_string_fields_exist(&info, &["table_name"])?;
+        let definition = match self.tables.read().await.deref().get(table_name) {
+            None => {return Err(errors::DbError::TableNotFound(String::from("Table does not exist")));}
+            Some(table) => {table.restore_definition(&info).await?}
+        };
+        let item_count = definition["data"].as_array().map_or(0, |d| d.len());
+        let mut table = table::Table::new(&definition).await?;
+        let mut guard = self.tables.write().await;
+        if guard.deref().contains_key(&table.table_name) {
+            return Err(errors::DbError::BadInput(format!("Table {} already exists", table.table_name)));
+        }
+        self.wal.append(serde_json::json!({"op": "put_table", "definition": table.to_snapshot().await?}), |lsn| table.mark_changed(lsn)).await?;
+        table.attach_wal(self.wal.clone());
+        guard.deref_mut().insert(table.table_name.clone(), Arc::new(table));
+        Ok(serde_json::json!({
+            "table_name": info["table_name"],
+            "item_count": item_count,
+        }))
+    }
+
     pub async fn read_table(&self, table_name: &String) -> Result<Value, errors::DbError> {
         let outer_guard = self.tables.read().await;
 
@@ -113,14 +222,94 @@ impl Database {
         }
     }
 
-    pub async fn update_table() {todo!();}
+    pub async fn describe_table(&self, table_name: &String) -> Result<Value, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                table.describe_table().await
+            }
+        }
+    }
+
+    // changes the table in place when it can; otherwise rebuilds it under the tables lock, where unless
+    // drop_unfitting is set, documents that don't fit the new key schema leave the table untouched and are only reported
+    pub async fn update_table(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
+        if info.get("table_name").is_some() {
+            validation::check_string_fields_exist(&info, &["table_name"])?;
+        }
+        let drop_unfitting = match info.get("drop_unfitting") {
+            None | Some(Value::Null) => {false}
+            Some(Value::Bool(b)) => {*b}
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("drop_unfitting must be a boolean")));}
+        };
+        {
+            let guard = self.tables.read().await;
+            let Some(table) = guard.deref().get(table_name) else {
+                return Err(errors::DbError::TableNotFound(String::from("Table does not exist")));
+            };
+            if table.updates_in_place(&info)? {
+                // logged once the change is made, as create_index is
+                table.update(&info).await?;
+                self.wal.append(serde_json::json!({"op": "update_table", "table": table_name, "changes": info}), |lsn| table.mark_changed(lsn)).await?;
+                return Ok(serde_json::json!({
+                    "updated": true,
+                    "unfitting_documents": [],
+                }));
+            }
+        }
+        let mut saves = self.saves.lock().await;
+        let mut guard = self.tables.write().await;
+        let Some(table) = guard.deref().get(table_name) else {
+            return Err(errors::DbError::TableNotFound(String::from("Table does not exist")));
+        };
+        let new_name = info.get("table_name").and_then(|n| n.as_str()).unwrap_or(table_name);
+        if new_name != table_name && guard.deref().contains_key(new_name) {
+            return Err(errors::DbError::BadInput(format!("Table {} already exists", new_name)));
+        }
+        let (mut table, unfitting) = table.rebuild(&info).await?;
+        let updated = unfitting.is_empty() || drop_unfitting;
+        if updated {
+            self.wal.append(serde_json::json!({
+                "op": "put_table",
+                "definition": table.to_snapshot().await?,
+                "replaces": table_name,
+            }), |lsn| table.mark_changed(lsn)).await?;
+            table.attach_wal(self.wal.clone());
+            guard.deref_mut().remove(table_name);
+            let renamed = table.table_name.ne(table_name);
+            guard.deref_mut().insert(table.table_name.clone(), Arc::new(table));
+            drop(guard);
+            if renamed {
+                self.remove_table_files(&mut saves, table_name);
+            }
+        }
+        Ok(serde_json::json!({
+            "updated": updated,
+            "unfitting_documents": unfitting,
+        }))
+    }
 
     pub async fn delete_table(&self, table_name: &String) ->  Result<Value, errors::DbError> {
+        let mut saves = self.saves.lock().await;
         let mut guard = self.tables.write().await;
+        if guard.deref().contains_key(table_name) {
+            self.wal.append(serde_json::json!({"op": "delete_table", "table": table_name}), |_| {}).await?;
+        }
         guard.deref_mut().remove(table_name);
+        drop(guard);
+        self.remove_table_files(&mut saves, table_name);
         Ok(NULL_VAL)
     }
 
+    // the drop is already logged, so a file that can't be removed now is left for the next save to retry
+    fn remove_table_files(&self, saves: &mut SaveState, table_name: &str) {
+        match snapshot::remove_table(&self.tables_dir(), &snapshot::file_stem(table_name)) {
+            Ok(_) => {saves.files.remove(table_name);}
+            Err(e) => {eprintln!("Could not remove the files of table {}: {}", table_name, e);}
+        }
+    }
+
 
     pub async fn create_document(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
         let guard = self.tables.read().await;
@@ -159,14 +348,553 @@ impl Database {
         }
     }
 
+    pub async fn query(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                table.query(info).await
+            }
+        }
+    }
+
+    pub async fn scan(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                table.scan(info).await
+            }
+        }
+    }
+
+    pub async fn create_index(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                // logged once the index exists, so a snapshot taken meanwhile can't miss it
+                let output = table.create_index(info.clone()).await?;
+                self.wal.append(serde_json::json!({"op": "create_index", "table": table_name, "definition": info}), |lsn| table.mark_changed(lsn)).await?;
+                Ok(output)
+            }
+        }
+    }
+
+    pub async fn delete_index(&self, table_name: &String, index_name: &String) -> Result<Value, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                let output = table.delete_index(index_name).await?;
+                self.wal.append(serde_json::json!({"op": "delete_index", "table": table_name, "index_name": index_name}), |lsn| table.mark_changed(lsn)).await?;
+                Ok(output)
+            }
+        }
+    }
+
+    pub async fn get_stream_cursor(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                table.get_stream_cursor(info).await
+            }
+        }
+    }
+
+    pub async fn read_stream(&self, table_name: &String, info: Value) -> Result<Value, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                table.read_stream(info).await
+            }
+        }
+    }
+
+    pub async fn subscribe(&self, table_name: &String, partition_key: Option<&str>) -> Result<subscriptions::Subscription, errors::DbError> {
+        let guard = self.tables.read().await;
+        match guard.deref().get(table_name) {
+            None => {Err(errors::DbError::TableNotFound(String::from("Table does not exist")))}
+            Some(table) => {
+                table.subscribe(partition_key)
+            }
+        }
+    }
+
+    // every item is checked against the current state before any of them is written; holding the
+    // tables lock exclusively keeps other operations from observing or interleaving with a partial commit
+    pub async fn transact_write(&self, info: Value) -> Result<Value, errors::DbError> {
+        let items = transaction_items(&info)?;
+        let guard = self.tables.write().await;
+
+        let mut requests = Vec::with_capacity(items.len());
+        let mut reasons = Vec::with_capacity(items.len());
+        let mut cancelled = false;
+        for item in items {
+            let prepared = prepare_item(guard.deref(), item, &requests).await;
+            match prepared {
+                Ok((table_name, request, new)) => {
+                    reasons.push(item_result(None));
+                    requests.push((table_name, request, new));
+                }
+                Err(e) => {
+                    cancelled = true;
+                    reasons.push(item_result(Some(&e)));
+                }
+            }
+        }
+        if cancelled {
+            return Err(errors::DbError::TransactionCanceled(Value::Array(reasons)));
+        }
+
+        // logged as one record before any of it is applied, so a crash or a failed append leaves none of it behind
+        let writes: Vec<(String, table::WriteRequest, Option<Value>)> = requests.into_iter()
+            .filter(|(_, request, _)| !request.is_check())
+            .collect();
+        if writes.is_empty() {
+            return Ok(NULL_VAL);
+        }
+        let timestamp = ttl::now();
+        let items: Vec<Value> = writes.iter()
+            .map(|(table_name, request, new)| guard.deref().get(table_name).unwrap().transaction_item(request, new))
+            .collect();
+        let table_names: BTreeSet<&String> = writes.iter().map(|(table_name, _, _)| table_name).collect();
+        self.wal.append(serde_json::json!({"op": "transact", "items": items, "timestamp": timestamp}), |lsn| {
+            for table_name in table_names {
+                let table_writes: Vec<(&table::WriteRequest, &Option<Value>)> = writes.iter()
+                    .filter(|(t, _, _)| t.eq(table_name))
+                    .map(|(_, request, new)| (request, new))
+                    .collect();
+                guard.deref().get(table_name).unwrap().transaction_logged(lsn, timestamp, &table_writes);
+            }
+        }).await?;
+        for (table_name, request, new) in writes {
+            guard.deref().get(&table_name).unwrap().commit(request, new).await?;
+        }
+        Ok(NULL_VAL)
+    }
+
+    pub async fn transact_get(&self, info: Value) -> Result<Value, errors::DbError> {
+        let items = transaction_items(&info)?;
+        let guard = self.tables.write().await;
+        let mut output = Vec::with_capacity(items.len());
+        for item in items {
+            validation::check_string_fields_exist(item, &["table_name"])?;
+            let table_name = item.get("table_name").unwrap().as_str().unwrap();
+            let Some(key) = item.get("key") else {
+                return Err(errors::DbError::MissingFields(String::from("key field is missing or null")));
+            };
+            match guard.deref().get(table_name) {
+                None => {return Err(errors::DbError::TableNotFound(format!("Table {} does not exist", table_name)));}
+                Some(table) => {output.push(table.read_document(key.clone()).await?);}
+            }
+        }
+        Ok(serde_json::json!({
+            "responses": output,
+        }))
+    }
+
+    pub async fn batch_get(&self, info: Value) -> Result<Value, errors::DbError> {
+        let requests = batch_tables(&info, "keys", BATCH_GET_LIMIT)?;
+        let guard = self.tables.read().await;
+        let mut responses = serde_json::Map::new();
+        let mut unprocessed = serde_json::Map::new();
+        for (table_name, keys) in requests {
+            let mut found = Vec::new();
+            let mut failed = Vec::new();
+            let mut results = Vec::new();
+            for key in keys {
+                let result = match guard.deref().get(table_name) {
+                    None => {Err(errors::DbError::TableNotFound(format!("Table {} does not exist", table_name)))}
+                    Some(table) => {table.read_document(key.clone()).await}
+                };
+                match result {
+                    Ok(document) => {
+                        results.push(item_result(None));
+                        if !document.is_null() {
+                            found.push(document);
+                        }
+                    }
+                    Err(e) => {
+                        results.push(item_result(Some(&e)));
+                        failed.push(key.clone());
+                    }
+                }
+            }
+            responses.insert(table_name.clone(), serde_json::json!({
+                "data": found,
+                "results": results,
+            }));
+            if !failed.is_empty() {
+                unprocessed.insert(table_name.clone(), serde_json::json!({"keys": failed}));
+            }
+        }
+        Ok(serde_json::json!({
+            "responses": responses,
+            "unprocessed_keys": unprocessed,
+        }))
+    }
+
+    // items are applied one at a time, so a failed item does not undo the others
+    pub async fn batch_write(&self, info: Value) -> Result<Value, errors::DbError> {
+        let requests = batch_tables(&info, "items", BATCH_WRITE_LIMIT)?;
+        let guard = self.tables.read().await;
+        let mut responses = serde_json::Map::new();
+        let mut unprocessed = serde_json::Map::new();
+        for (table_name, items) in requests {
+            let mut failed = Vec::new();
+            let mut results = Vec::new();
+            for item in items {
+                let result = match guard.deref().get(table_name) {
+                    None => {Err(errors::DbError::TableNotFound(format!("Table {} does not exist", table_name)))}
+                    Some(table) => {
+                        match (item.get("put"), item.get("delete")) {
+                            (Some(document), None) => {table.create_document(document.clone()).await}
+                            (None, Some(key)) => {table.delete_document(key.clone()).await}
+                            _ => {Err(errors::DbError::BadInput(String::from("Each item must be exactly one of put or delete")))}
+                        }
+                    }
+                };
+                match result {
+                    Ok(_) => {results.push(item_result(None));}
+                    Err(e) => {
+                        results.push(item_result(Some(&e)));
+                        failed.push(item.clone());
+                    }
+                }
+            }
+            responses.insert(table_name.clone(), serde_json::json!({"results": results}));
+            if !failed.is_empty() {
+                unprocessed.insert(table_name.clone(), Value::Array(failed));
+            }
+        }
+        Ok(serde_json::json!({
+            "responses": responses,
+            "unprocessed_items": unprocessed,
+        }))
+    }
+
+    pub async fn sweep_expired(&self) -> Value {
+        let now = ttl::now();
+        let guard = self.tables.read().await;
+        let mut deleted = serde_json::Map::new();
+        for (table_name, table) in guard.deref() {
+            let keys = table.sweep_expired(now).await;
+            if !keys.is_empty() {
+                deleted.insert(table_name.clone(), Value::Array(keys));
+            }
+        }
+        serde_json::json!({
+            "deleted": deleted,
+        })
+    }
+
+    // writes each table changed since its file was last written, keeping the file it replaces as a fallback, and
+    // brings the fallbacks of tables that stopped changing up to date. the log keeps every record after the previous
+    // save, which is all any fallback needs. writes made while a table is read may land in both its file and the log,
+    // which replaying them again on startup makes harmless
     pub async fn save(&self) {
-        let mut output = Vec::new();
+        let mut saves = self.saves.lock().await;
+        let dir = self.tables_dir();
+
+        // the tables lock is only held to pick the tables to write; tables can't be dropped or renamed
+        // while the save state is held, and document writes only wait on the partition being read
+        let mut changed = Vec::new();
+        let mut settled = Vec::new();
         let outer_guard = self.tables.read().await;
-        for (_, table_obj) in outer_guard.deref() {
-            let table_data = table_obj.read_table().await.unwrap();
-            output.push(table_data);
+        let lsn = self.wal.last_lsn();
+        for (table_name, table_obj) in outer_guard.deref() {
+            match saves.files.get(table_name) {
+                Some(files) if table_obj.changed_lsn() <= files.saved_lsn => {
+                    if files.prev_lsn < files.saved_lsn {
+                        settled.push(table_name.clone());
+                    }
+                }
+                _ => {changed.push((table_name.clone(), table_obj.clone()));}
+            }
+        }
+        let names: HashSet<String> = outer_guard.deref().keys().cloned().collect();
+        drop(outer_guard);
+
+        let mut failed = false;
+        for (table_name, table_obj) in changed {
+            let stem = snapshot::file_stem(&table_name);
+            // backups go first and reach at least as far as the table file, whose lsn stops them being replayed
+            if let Some(backups) = table_obj.backups()
+                && let Some(file) = backups.to_file() {
+                let (dir, stem) = (dir.clone(), stem.clone());
+                let (written, file) = blocking(move || (snapshot::write_backups(&dir, &stem, &file), file)).await;
+                match written {
+                    Ok(_) => {backups.saved(&file);}
+                    Err(e) => {
+                        backups.save_failed();
+                        failed = true;
+                        self.status.lock().unwrap().error = Some(format!("Could not save the backups of table {}: {}", table_name, e));
+                        continue;
+                    }
+                }
+            }
+            let definition = table_obj.read_table().await.unwrap();
+            // a table's first file is copied to its fallback straight away, the log may not reach back to its creation
+            let previous = saves.files.get(&table_name).map(|files| files.saved_lsn);
+            let dir = dir.clone();
+            let written = blocking(move || {
+                snapshot::write_table(&dir, &stem, lsn, &definition)
+                    .and_then(|_| if previous.is_none() {snapshot::refresh_prev(&dir, &stem)} else {Ok(())})
+            }).await;
+            match written {
+                Ok(_) => {
+                    saves.files.insert(table_name, snapshot::TableFiles {saved_lsn: lsn, prev_lsn: previous.unwrap_or(lsn)});
+                }
+                Err(e) => {
+                    failed = true;
+                    self.status.lock().unwrap().error = Some(format!("Could not save table {}: {}", table_name, e));
+                }
+            }
+        }
+        for table_name in settled {
+            let (dir, stem) = (dir.clone(), snapshot::file_stem(&table_name));
+            match blocking(move || snapshot::refresh_prev(&dir, &stem)).await {
+                Ok(_) => {
+                    let files = saves.files.get_mut(&table_name).unwrap();
+                    files.prev_lsn = files.saved_lsn;
+                }
+                Err(e) => {
+                    failed = true;
+                    self.status.lock().unwrap().error = Some(format!("Could not save table {}: {}", table_name, e));
+                }
+            }
         }
-        let p = std::path::Path::new(self.path.as_str()).join("data.json");
-        let _ = std::fs::write(p, serde_json::to_vec(&output).unwrap());
+
+        // files of tables dropped or renamed without their files being removed
+        let stems: HashSet<String> = names.iter().map(|name| snapshot::file_stem(name)).collect();
+        saves.files.retain(|table_name, _| names.contains(table_name));
+        blocking(move || match snapshot::stems(&dir) {
+            Ok(found) => {
+                for stem in found.iter().filter(|stem| !stems.contains(*stem)) {
+                    if let Err(e) = snapshot::remove_table(&dir, stem) {
+                        eprintln!("Could not remove the files of {}: {}", stem, e);
+                    }
+                }
+            }
+            Err(e) => {eprintln!("{}", e);}
+        }).await;
+
+        if failed {
+            eprintln!("{}", self.status.lock().unwrap().error.as_deref().unwrap_or_default());
+            return;
+        }
+        if saves.legacy {
+            let path = self.path.clone();
+            match blocking(move || snapshot::remove_legacy(std::path::Path::new(path.as_str()))).await {
+                Ok(_) => {saves.legacy = false;}
+                Err(e) => {eprintln!("Could not remove the old snapshot: {}", e);}
+            }
+        }
+        if let Err(e) = self.wal.truncate(saves.lsn).await {
+            eprintln!("{}", e);
+        }
+        saves.lsn = lsn;
+        *self.status.lock().unwrap() = SaveStatus {
+            saved_at: Some(ttl::now()),
+            lsn,
+            error: None,
+        };
+    }
+
+    fn unsaved_writes(&self) -> u64 {
+        self.wal.last_lsn().saturating_sub(self.status.lock().unwrap().lsn)
+    }
+
+    // saves every seconds seconds, and as soon as mutations writes are waiting to be saved; 0 turns either off
+    pub async fn autosave(&self, seconds: u64, mutations: u64) {
+        if seconds == 0 && mutations == 0 {
+            return;
+        }
+        let period = std::time::Duration::from_secs(seconds);
+        let mut deadline = tokio::time::Instant::now() + period;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline), if seconds > 0 => {}
+                _ = self.wal.appended(), if mutations > 0 => {
+                    if self.unsaved_writes() < mutations {
+                        continue;
+                    }
+                }
+            }
+            if self.unsaved_writes() > 0 {
+                self.save().await;
+            }
+            deadline = tokio::time::Instant::now() + period;
+        }
+    }
+
+    // lets the periodic task fsync writes the interval sync policy hasn't yet
+    pub async fn sync_wal(&self) -> Result<(), errors::DbError> {
+        self.wal.sync().await
+    }
+
+    pub fn save_status(&self) -> Value {
+        let status = self.status.lock().unwrap();
+        serde_json::json!({
+            "last_saved_at": status.saved_at,
+            "unsaved_writes": self.wal.last_lsn().saturating_sub(status.lsn),
+            "last_error": status.error,
+        })
+    }
+}
+
+// file work runs on the blocking pool, so a slow disk doesn't stall the workers serving requests
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(work).await.unwrap()
+}
+
+// applies one write-ahead log record to the tables being loaded, unless the files they came from already cover it
+async fn replay(tables: &mut HashMap<String, table::Table>, record: &Value, covered: impl Fn(&str) -> bool) -> Result<(), errors::DbError> {
+    let table_name = record.get("table").and_then(|t| t.as_str()).unwrap_or_default();
+    match record.get("op").and_then(|o| o.as_str()) {
+        Some("put_table") => {
+            if let Some(old_name) = record.get("replaces").and_then(|n| n.as_str()) && !covered(old_name) {
+                tables.remove(old_name);
+            }
+            if covered(record["definition"]["table_name"].as_str().unwrap_or_default()) {
+                return Ok(());
+            }
+            let table = table::Table::new(&record["definition"]).await?;
+            table.mark_changed(record["lsn"].as_u64().unwrap_or_default());
+            tables.insert(table.table_name.clone(), table);
+            Ok(())
+        }
+        Some("delete_table") => {
+            if !covered(table_name) {
+                tables.remove(table_name);
+            }
+            Ok(())
+        }
+        // each table takes its part of the transaction, unless its file already covers it
+        Some("transact") => {
+            let items = record.get("items").and_then(|i| i.as_array()).map_or(&[][..], |i| i.as_slice());
+            let mut table_names: Vec<&str> = items.iter().map(|item| item["table"].as_str().unwrap_or_default()).collect();
+            table_names.sort();
+            table_names.dedup();
+            for table_name in table_names {
+                // tables dropped since the transaction are left out
+                let Some(table) = tables.get(table_name).filter(|_| !covered(table_name)) else {
+                    continue;
+                };
+                let writes: Vec<&Value> = items.iter().filter(|item| item["table"].eq(table_name)).collect();
+                table.replay(record, &writes).await?;
+            }
+            Ok(())
+        }
+        _ => {
+            if covered(table_name) {
+                return Ok(());
+            }
+            let Some(table) = tables.get(table_name) else {
+                return Err(errors::DbError::TableNotFound(format!("Table {} does not exist", table_name)));
+            };
+            match record.get("op").and_then(|o| o.as_str()) {
+                Some("create_index") => {
+                    table.create_index(record["definition"].clone()).await?;
+                    table.mark_changed(record["lsn"].as_u64().unwrap_or_default());
+                }
+                Some("delete_index") => {
+                    table.delete_index(&record["index_name"].as_str().unwrap_or_default().to_string()).await?;
+                    table.mark_changed(record["lsn"].as_u64().unwrap_or_default());
+                }
+                Some("update_table") => {
+                    table.update(&record["changes"]).await?;
+                    table.mark_changed(record["lsn"].as_u64().unwrap_or_default());
+                }
+                _ => {table.replay(record, &[record]).await?;}
+            }
+            Ok(())
+        }
+    }
+}
+
+const TRANSACTION_LIMIT: usize = 100;
+
+fn transaction_items(info: &Value) -> Result<&Vec<Value>, errors::DbError> {
+    match info.get("items") {
+        None | Some(Value::Null) => {Err(errors::DbError::MissingFields(String::from("items field is missing or null")))}
+        Some(Value::Array(items)) => {
+            if items.is_empty() || items.len() > TRANSACTION_LIMIT {
+                return Err(errors::DbError::BadInput(format!("items must hold between 1 and {} operations", TRANSACTION_LIMIT)));
+            }
+            Ok(items)
+        }
+        Some(_) => {Err(errors::DbError::BadInput(String::from("items must be a valid JSON array")))}
+    }
+}
+
+async fn prepare_item(
+    tables: &HashMap<String, Arc<table::Table>>,
+    item: &Value,
+    prepared: &[(String, table::WriteRequest, Option<Value>)],
+) -> Result<(String, table::WriteRequest, Option<Value>), errors::DbError> {
+    let operations = [
+        ("put", table::WriteKind::Put),
+        ("update", table::WriteKind::Update),
+        ("delete", table::WriteKind::Delete),
+        ("condition_check", table::WriteKind::ConditionCheck),
+    ];
+    let Some((name, kind)) = operations.into_iter().find(|(name, _)| item.get(name).is_some()) else {
+        return Err(errors::DbError::BadInput(String::from("Each item must be one of put, update, delete or condition_check")));
+    };
+    let body = item.get(name).unwrap();
+    validation::check_string_fields_exist(body, &["table_name"])?;
+    let table_name = body.get("table_name").unwrap().as_str().unwrap().to_string();
+    let Some(table) = tables.get(&table_name) else {
+        return Err(errors::DbError::TableNotFound(format!("Table {} does not exist", table_name)));
+    };
+    let request = table.parse_write(kind, body)?;
+    if prepared.iter().any(|(t, r, _)| t.eq(&table_name) && r.key() == request.key()) {
+        return Err(errors::DbError::BadInput(String::from("A transaction cannot include multiple operations on one document")));
+    }
+    let new = table.prepare(&request).await?;
+    Ok((table_name, request, new))
+}
+
+fn item_result(e: Option<&errors::DbError>) -> Value {
+    let Some(e) = e else {
+        return serde_json::json!({"code": "None"});
+    };
+    let code = match e {
+        errors::DbError::ConditionFailed(_) => {"ConditionalCheckFailed"}
+        errors::DbError::TableNotFound(_) => {"TableNotFound"}
+        errors::DbError::StorageError(_) => {"InternalServerError"}
+        _ => {"ValidationError"}
+    };
+    serde_json::json!({
+        "code": code,
+        "message": e.to_string(),
+    })
+}
+
+const BATCH_GET_LIMIT: usize = 100;
+const BATCH_WRITE_LIMIT: usize = 25;
+
+// batches look like {"tables": {table_name: [...]}} for writes or {"tables": {table_name: {"keys": [...]}}} for gets
+fn batch_tables<'a>(info: &'a Value, field: &str, limit: usize) -> Result<Vec<(&'a String, &'a Vec<Value>)>, errors::DbError> {
+    let Some(tables) = info.get("tables").and_then(|t| t.as_object()) else {
+        return Err(errors::DbError::BadInput(String::from("tables must be a valid JSON object")));
+    };
+    let mut output = Vec::new();
+    let mut count = 0;
+    for (table_name, request) in tables {
+        let items = if field.eq("keys") {request.get(field)} else {Some(request)};
+        let Some(items) = items.and_then(|i| i.as_array()) else {
+            return Err(errors::DbError::BadInput(format!("{} for table {} must be a valid JSON array", field, table_name)));
+        };
+        count += items.len();
+        output.push((table_name, items));
+    }
+    if count == 0 || count > limit {
+        return Err(errors::DbError::BadInput(format!("A batch must hold between 1 and {} {}", limit, field)));
     }
+    Ok(output)
 }
@@ -1,12 +1,18 @@
+use base64::Engine;
 use crate::*;
 
+#[derive(Clone)]
 pub enum KeyDatatype {
     Null(()),
     Boolean(bool),
     SignedInt(i64),
     UnsignedInt(u64),
-    // Float(f64),
+    Float(f64),
     String(String),
+    // base64 encoded in documents, compared bytewise
+    Binary(Vec<u8>),
+    // one value per attribute of a composite key, compared lexicographically
+    Composite(Vec<KeyDatatype>),
 }
 
 impl KeyDatatype {
@@ -16,8 +22,23 @@ impl KeyDatatype {
             KeyDatatype::Boolean(_) => {"Boolean"}
             KeyDatatype::SignedInt(_) => {"SignedInt"}
             KeyDatatype::UnsignedInt(_) => {"UnsignedInt"}
-            // KeyDatatype::Float(_) => {"Float"}
+            KeyDatatype::Float(_) => {"Float"}
             KeyDatatype::String(_) => {"String"}
+            KeyDatatype::Binary(_) => {"Binary"}
+            KeyDatatype::Composite(_) => {"Composite"}
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            KeyDatatype::Null(_) => {Value::Null}
+            KeyDatatype::Boolean(b) => {Value::Bool(*b)}
+            KeyDatatype::SignedInt(n) => {Value::from(*n)}
+            KeyDatatype::UnsignedInt(n) => {Value::from(*n)}
+            KeyDatatype::Float(n) => {Value::from(*n)}
+            KeyDatatype::String(s) => {Value::String(s.clone())}
+            KeyDatatype::Binary(b) => {Value::String(base64::engine::general_purpose::STANDARD.encode(b))}
+            KeyDatatype::Composite(values) => {Value::Array(values.iter().map(|v| v.to_value()).collect())}
         }
     }
 
@@ -27,159 +48,54 @@ impl KeyDatatype {
             KeyDatatype::Boolean(_) => {1}
             KeyDatatype::SignedInt(_) => {2}
             KeyDatatype::UnsignedInt(_) => {3}
-            // KeyDatatype::Float(_) => {4}
+            KeyDatatype::Float(_) => {4}
             KeyDatatype::String(_) => {5}
+            KeyDatatype::Binary(_) => {6}
+            KeyDatatype::Composite(_) => {7}
         }
     }
 
-    pub fn from_int(n: u8) -> Self {
-        match n {
-            1 => {Self::Boolean(false)}
-            2 => {Self::SignedInt(0)}
-            3 => {Self::UnsignedInt(0)}
-            // 4 => {Self::Float(0.)}
-            5 => {Self::String(String::new())}
-            _ => {Self::Null(())}
+    pub fn from_str(s: &str) -> Result<Self, errors::DbError> {
+        match s {
+            "Null" => {Ok(Self::Null(()))}
+            "Boolean" => {Ok(Self::Boolean(false))}
+            "UnsignedInt" => {Ok(Self::UnsignedInt(0))}
+            "SignedInt" => {Ok(Self::SignedInt(0))}
+            "Float" => {Ok(Self::Float(0.))}
+            "String" => {Ok(Self::String(String::new()))}
+            "Binary" => {Ok(Self::Binary(Vec::new()))}
+            _ => {Err(errors::DbError::BadInput(format!("Unknown datatype {}, expected one of Null, Boolean, SignedInt, UnsignedInt, Float, String or Binary", s)))}
         }
     }
-
-    pub fn from_str(s: &str) -> Self {
-        if s.eq("Boolean") {Self::Boolean(false)}
-        else if s.eq("UnsignedInt") {Self::UnsignedInt(0)}
-        else if s.eq("SignedInt") {Self::SignedInt(0)}
-        // else if s.eq("Float") {Self::Float(0.)}
-        else if s.eq("String") {Self::String(String::new())}
-        else {Self::Null(())}
-    }
 }
 
 impl Eq for KeyDatatype {}
 
 impl PartialEq<Self> for KeyDatatype {
     fn eq(&self, other: &Self) -> bool {
-        match self {
-            KeyDatatype::Null(s) => {
-                match other {
-                    KeyDatatype::Null(o) => {s.eq(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::Boolean(s) => {
-                match other {
-                    KeyDatatype::Boolean(o) => {s.eq(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::SignedInt(s) => {
-                match other {
-                    KeyDatatype::SignedInt(o) => {s.eq(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::UnsignedInt(s) => {
-                match other {
-                    KeyDatatype::UnsignedInt(o) => {s.eq(o)}
-                    _ => {panic!();}
-                }
-            }
-            // KeyDatatype::Float(s) => {
-            //     match other {
-            //         KeyDatatype::Float(o) => {s.eq(o)}
-            //         _ => {panic!();}
-            //     }
-            // }
-            KeyDatatype::String(s) => {
-                match other {
-                    KeyDatatype::String(o) => {s.eq(o)}
-                    _ => {panic!();}
-                }
-            }
-        }
+        self.cmp(other).is_eq()
     }
 }
 
 impl PartialOrd<Self> for KeyDatatype {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self {
-            KeyDatatype::Null(s) => {
-                match other {
-                    KeyDatatype::Null(o) => {s.partial_cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::Boolean(s) => {
-                match other {
-                    KeyDatatype::Boolean(o) => {s.partial_cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::SignedInt(s) => {
-                match other {
-                    KeyDatatype::SignedInt(o) => {s.partial_cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::UnsignedInt(s) => {
-                match other {
-                    KeyDatatype::UnsignedInt(o) => {s.partial_cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            // KeyDatatype::Float(s) => {
-            //     match other {
-            //         KeyDatatype::Float(o) => {s.partial_cmp(o)}
-            //         _ => {panic!();}
-            //     }
-            // }
-            KeyDatatype::String(s) => {
-                match other {
-                    KeyDatatype::String(o) => {s.partial_cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-        }
+        Some(self.cmp(other))
     }
 }
 
+// keys of different datatypes never come from one key schema, but if they meet anyway they order by datatype
 impl Ord for KeyDatatype {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match self {
-            KeyDatatype::Null(s) => {
-                match other {
-                    KeyDatatype::Null(o) => {s.cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::Boolean(s) => {
-                match other {
-                    KeyDatatype::Boolean(o) => {s.cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::SignedInt(s) => {
-                match other {
-                    KeyDatatype::SignedInt(o) => {s.cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            KeyDatatype::UnsignedInt(s) => {
-                match other {
-                    KeyDatatype::UnsignedInt(o) => {s.cmp(o)}
-                    _ => {panic!();}
-                }
-            }
-            // KeyDatatype::Float(s) => {
-            //     match other {
-            //         KeyDatatype::Float(o) => {s.cmp(o)}
-            //         _ => {panic!();}
-            //     }
-            // }
-            KeyDatatype::String(s) => {
-                match other {
-                    KeyDatatype::String(o) => {s.cmp(o)}
-                    _ => {panic!();}
-                }
-            }
+        match (self, other) {
+            (KeyDatatype::Null(s), KeyDatatype::Null(o)) => {s.cmp(o)}
+            (KeyDatatype::Boolean(s), KeyDatatype::Boolean(o)) => {s.cmp(o)}
+            (KeyDatatype::SignedInt(s), KeyDatatype::SignedInt(o)) => {s.cmp(o)}
+            (KeyDatatype::UnsignedInt(s), KeyDatatype::UnsignedInt(o)) => {s.cmp(o)}
+            (KeyDatatype::Float(s), KeyDatatype::Float(o)) => {s.total_cmp(o)}
+            (KeyDatatype::String(s), KeyDatatype::String(o)) => {s.cmp(o)}
+            (KeyDatatype::Binary(s), KeyDatatype::Binary(o)) => {s.cmp(o)}
+            (KeyDatatype::Composite(s), KeyDatatype::Composite(o)) => {s.cmp(o)}
+            _ => {self.to_int().cmp(&other.to_int())}
         }
     }
 }
@@ -191,8 +107,10 @@ impl std::hash::Hash for KeyDatatype {
             KeyDatatype::Boolean(s) => {s.hash(state);}
             KeyDatatype::SignedInt(s) => {s.hash(state);}
             KeyDatatype::UnsignedInt(s) => {s.hash(state);}
-            // KeyDatatype::Float(s) => {s.hash(state);}
+            KeyDatatype::Float(s) => {s.to_bits().hash(state);}
             KeyDatatype::String(s) => {s.hash(state);}
+            KeyDatatype::Binary(s) => {s.hash(state);}
+            KeyDatatype::Composite(s) => {s.hash(state);}
         }
     }
 }
@@ -200,6 +118,123 @@ impl std::hash::Hash for KeyDatatype {
 pub struct Key {
     pub name: String,
     pub datatype: KeyDatatype,
+    // where the attribute sits in a document, parsed from the name
+    pub path: Vec<String>,
+    // the attributes of a composite key, in comparison order; empty for single attribute keys
+    pub components: Vec<Key>,
+}
+
+// nested attributes are opt in: an explicit path of attribute names, or a name starting with / read as a JSON Pointer;
+// any other name is a top level attribute, dots included
+fn parse_path(name: &str, path: Option<&Value>) -> Result<Vec<String>, errors::DbError> {
+    match path {
+        None | Some(Value::Null) => {}
+        Some(Value::Array(segments)) if !segments.is_empty() => {
+            return segments.iter().map(|segment| match segment.as_str() {
+                Some(segment) if !segment.is_empty() => {Ok(segment.to_string())}
+                _ => {Err(errors::DbError::BadInput(format!("Key path of {} must hold non-empty strings", name)))}
+            }).collect();
+        }
+        Some(_) => {return Err(errors::DbError::BadInput(format!("Key path of {} must be a non-empty array of attribute names", name)));}
+    }
+    if let Some(pointer) = name.strip_prefix('/') {
+        return Ok(pointer.split('/').map(|s| s.replace("~1", "/").replace("~0", "~")).collect());
+    }
+    Ok(vec![name.to_string()])
+}
+
+impl Key {
+    // expects a definition already checked by validation::check_key_fields_exist
+    pub fn from_json(info: &Value) -> Result<Self, errors::DbError> {
+        let Value::Array(attributes) = info else {
+            let name = info.get("name").unwrap().as_str().unwrap();
+            return Ok(Self {
+                name: name.to_string(),
+                datatype: KeyDatatype::from_str(info.get("datatype").unwrap().as_str().unwrap())?,
+                path: parse_path(name, info.get("path"))?,
+                components: Vec::new(),
+            });
+        };
+        let mut components: Vec<Key> = Vec::new();
+        for attribute in attributes {
+            let component = Self::from_json(attribute)?;
+            if matches!(component.datatype, KeyDatatype::Null(_)) {
+                return Err(errors::DbError::BadInput(String::from("Composite key attributes cannot be Null")));
+            }
+            if components.iter().any(|c| c.path == component.path) {
+                return Err(errors::DbError::BadInput(format!("Attribute {} appears twice in a composite key", component.name)));
+            }
+            components.push(component);
+        }
+        Ok(Self {
+            name: components.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>().join(", "),
+            datatype: KeyDatatype::Composite(components.iter().map(|c| c.datatype.clone()).collect()),
+            path: Vec::new(),
+            components,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        if !self.components.is_empty() {
+            return Value::Array(self.components.iter().map(|c| c.to_json()).collect());
+        }
+        let mut output = serde_json::json!({
+            "name": self.name,
+            "datatype": self.datatype.to_str(),
+        });
+        if !self.name.starts_with('/') && self.path != [self.name.as_str()] {
+            output["path"] = Value::from(self.path.clone());
+        }
+        output
+    }
+
+    // the single attribute keys a key is read from: its components, or the key itself
+    pub fn attributes(&self) -> Vec<&Key> {
+        if self.components.is_empty() {
+            vec![self]
+        } else {
+            self.components.iter().collect()
+        }
+    }
+
+    pub fn lookup<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.path.iter().try_fold(document, |current, segment| current.as_object()?.get(segment))
+    }
+
+    // sets the value at the key's path, creating the objects along the way
+    fn insert_value(&self, value: Value, output: &mut serde_json::Map<String, Value>) {
+        let (last, parents) = self.path.split_last().unwrap();
+        let mut current = output;
+        for segment in parents {
+            let entry = current.entry(segment.clone()).or_insert_with(|| Value::Object(serde_json::Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(serde_json::Map::new());
+            }
+            current = entry.as_object_mut().unwrap();
+        }
+        current.insert(last.clone(), value);
+    }
+
+    // copies the key's attributes from a document into another, at the same paths
+    pub fn copy_attributes(&self, document: &Value, output: &mut serde_json::Map<String, Value>) {
+        for attribute in self.attributes() {
+            if let Some(value) = attribute.lookup(document) {
+                attribute.insert_value(value.clone(), output);
+            }
+        }
+    }
+
+    // writes a key value back out as the document attributes it came from
+    pub fn insert_attributes(&self, value: &KeyDatatype, output: &mut serde_json::Map<String, Value>) {
+        match value {
+            KeyDatatype::Composite(values) => {
+                for (component, value) in self.components.iter().zip(values) {
+                    component.insert_attributes(value, output);
+                }
+            }
+            _ => {self.insert_value(value.to_value(), output);}
+        }
+    }
 }
 
 
@@ -219,20 +254,25 @@ fn validate(key: &Key, info: &Value) -> Result<(), errors::DbError> {
     match key.datatype {
         KeyDatatype::Null(_) => { Ok(()) }
         KeyDatatype::Boolean(_) => {
-            if info.get(&key.name).is_some() && info.get(&key.name).unwrap().is_boolean() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
+            if key.lookup(info).is_some() && key.lookup(info).unwrap().is_boolean() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
         }
         KeyDatatype::SignedInt(_) => {
-            if info.get(&key.name).is_some() && info.get(&key.name).unwrap().is_i64() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
+            if key.lookup(info).is_some() && key.lookup(info).unwrap().is_i64() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
         }
         KeyDatatype::UnsignedInt(_) => {
-            if info.get(&key.name).is_some() && info.get(&key.name).unwrap().is_u64() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
+            if key.lookup(info).is_some() && key.lookup(info).unwrap().is_u64() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
+        }
+        KeyDatatype::Float(_) => {
+            if key.lookup(info).is_some() && key.lookup(info).unwrap().is_number() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
         }
-        // KeyDatatype::Float(_) => {
-        //     if info.get(&key.name).is_some() && info.get(&key.name).unwrap().is_f64() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
-        // }
         KeyDatatype::String(_) => {
-            if info.get(&key.name).is_some() && info.get(&key.name).unwrap().is_string() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
+            if key.lookup(info).is_some() && key.lookup(info).unwrap().is_string() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
         }
+        KeyDatatype::Binary(_) => {
+            if key.lookup(info).is_some() && key.lookup(info).unwrap().is_string() { Ok(()) } else { Err(errors::DbError::MissingFields(format!("Field {} must exist and be of type {}.", &key.name, key.datatype.to_str()))) }
+        }
+        // each attribute is validated as it is extracted
+        KeyDatatype::Composite(_) => { Ok(()) }
     }
 }
 
@@ -243,10 +283,61 @@ pub fn extract_key(key: &Key, info: &Value) -> Result<KeyDatatype, errors::DbErr
     }
     Ok(match key.datatype {
         KeyDatatype::Null(_) => {KeyDatatype::Null(())}
-        KeyDatatype::Boolean(_) => { KeyDatatype::Boolean(info.get(&key.name).unwrap().as_bool().unwrap()) }
-        KeyDatatype::SignedInt(_) => { KeyDatatype::SignedInt(info.get(&key.name).unwrap().as_i64().unwrap()) }
-        KeyDatatype::UnsignedInt(_) => {KeyDatatype::UnsignedInt(info.get(&key.name).unwrap().as_u64().unwrap())}
-        // KeyDatatype::Float(_) => {KeyDatatype::Float(info.get(&key.name).unwrap().as_f64().unwrap())}
-        KeyDatatype::String(_) => {KeyDatatype::String(String::from(info.get(&key.name).unwrap().as_str().unwrap()))}
+        KeyDatatype::Boolean(_) => { KeyDatatype::Boolean(key.lookup(info).unwrap().as_bool().unwrap()) }
+        KeyDatatype::SignedInt(_) => { KeyDatatype::SignedInt(key.lookup(info).unwrap().as_i64().unwrap()) }
+        KeyDatatype::UnsignedInt(_) => {KeyDatatype::UnsignedInt(key.lookup(info).unwrap().as_u64().unwrap())}
+        KeyDatatype::Float(_) => {parse_value(key, key.lookup(info).unwrap())?}
+        KeyDatatype::String(_) => {KeyDatatype::String(String::from(key.lookup(info).unwrap().as_str().unwrap()))}
+        KeyDatatype::Binary(_) => {parse_value(key, key.lookup(info).unwrap())?}
+        KeyDatatype::Composite(_) => {
+            let mut values = Vec::with_capacity(key.components.len());
+            for component in &key.components {
+                values.push(extract_key(component, info)?);
+            }
+            KeyDatatype::Composite(values)
+        }
     })
 }
+
+
+pub fn parse_value(key: &Key, value: &Value) -> Result<KeyDatatype, errors::DbError> {
+    let mismatch = || errors::DbError::BadInput(format!("Value for {} must be of type {}.", &key.name, key.datatype.to_str()));
+    match key.datatype {
+        KeyDatatype::Null(_) => {
+            if value.is_null() { Ok(KeyDatatype::Null(())) } else { Err(mismatch()) }
+        }
+        KeyDatatype::Boolean(_) => { value.as_bool().map(KeyDatatype::Boolean).ok_or_else(mismatch) }
+        KeyDatatype::SignedInt(_) => { value.as_i64().map(KeyDatatype::SignedInt).ok_or_else(mismatch) }
+        KeyDatatype::UnsignedInt(_) => { value.as_u64().map(KeyDatatype::UnsignedInt).ok_or_else(mismatch) }
+        KeyDatatype::Float(_) => { value.as_f64().and_then(float_key).ok_or_else(mismatch) }
+        KeyDatatype::String(_) => { value.as_str().map(|s| KeyDatatype::String(String::from(s))).ok_or_else(mismatch) }
+        KeyDatatype::Binary(_) => {
+            let Some(s) = value.as_str() else {return Err(mismatch());};
+            base64::engine::general_purpose::STANDARD.decode(s).map(KeyDatatype::Binary)
+                .map_err(|_| errors::DbError::BadInput(format!("Value for {} must be valid base64.", &key.name)))
+        }
+        KeyDatatype::Composite(_) => {
+            let values = match value.as_array() {
+                Some(values) if values.len() == key.components.len() => {values}
+                _ => {return Err(errors::DbError::BadInput(format!("Value for {} must be an array of {} values.", &key.name, key.components.len())));}
+            };
+            let mut output = Vec::with_capacity(values.len());
+            for (component, value) in key.components.iter().zip(values) {
+                output.push(parse_value(component, value)?);
+            }
+            Ok(KeyDatatype::Composite(output))
+        }
+    }
+}
+
+
+// NaN has no place in the key order, and -0.0 is stored as 0.0 so the two are the same key
+fn float_key(n: f64) -> Option<KeyDatatype> {
+    if n.is_nan() {
+        None
+    } else if n == 0. {
+        Some(KeyDatatype::Float(0.))
+    } else {
+        Some(KeyDatatype::Float(n))
+    }
+}
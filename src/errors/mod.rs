@@ -3,7 +3,11 @@ use std::fmt::Formatter;
 pub enum DbError {
     MissingFields(String),
     TableNotFound(String),
+    IndexNotFound(String),
     BadInput(String),
+    ConditionFailed(String),
+    TransactionCanceled(serde_json::Value),
+    StorageError(String),
 }
 
 impl DbError {
@@ -17,10 +21,26 @@ impl DbError {
                 {
                     write!(f, "Table Not Found! {}", message)
                 }
+            DbError::IndexNotFound(message) =>
+                {
+                    write!(f, "Index Not Found! {}", message)
+                }
             DbError::BadInput(message) =>
                 {
                     write!(f, "Bad Input! {}", message)
                 }
+            DbError::ConditionFailed(message) =>
+                {
+                    write!(f, "Condition Failed! {}", message)
+                }
+            DbError::TransactionCanceled(reasons) =>
+                {
+                    write!(f, "Transaction Canceled! {}", reasons)
+                }
+            DbError::StorageError(message) =>
+                {
+                    write!(f, "Storage Error! {}", message)
+                }
         }
     }
 }
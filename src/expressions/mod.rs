@@ -0,0 +1,631 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use crate::*;
+
+#[derive(Clone, PartialEq)]
+pub enum Token {
+    Identifier(String),
+    Name(String),
+    Value(String),
+    Number(usize),
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Dot,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Plus,
+    Minus,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, errors::DbError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut output = Vec::new();
+    let mut i = 0;
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        match c {
+            '(' => {output.push(Token::LeftParen); i += 1;}
+            ')' => {output.push(Token::RightParen); i += 1;}
+            '[' => {output.push(Token::LeftBracket); i += 1;}
+            ']' => {output.push(Token::RightBracket); i += 1;}
+            ',' => {output.push(Token::Comma); i += 1;}
+            '.' => {output.push(Token::Dot); i += 1;}
+            '=' => {output.push(Token::Equal); i += 1;}
+            '+' => {output.push(Token::Plus); i += 1;}
+            '-' => {output.push(Token::Minus); i += 1;}
+            '<' => {
+                if chars.get(i + 1) == Some(&'>') {output.push(Token::NotEqual); i += 2;}
+                else if chars.get(i + 1) == Some(&'=') {output.push(Token::LessEqual); i += 2;}
+                else {output.push(Token::Less); i += 1;}
+            }
+            '>' => {
+                if chars.get(i + 1) == Some(&'=') {output.push(Token::GreaterEqual); i += 2;}
+                else {output.push(Token::Greater); i += 1;}
+            }
+            '#' | ':' => {
+                i += 1;
+                while i < chars.len() && is_word(chars[i]) {i += 1;}
+                if i == start + 1 {
+                    return Err(errors::DbError::BadInput(format!("Expected a placeholder name after {} at position {}", c, start)));
+                }
+                let s: String = chars[start..i].iter().collect();
+                output.push(if c == '#' {Token::Name(s)} else {Token::Value(s)});
+            }
+            _ if c.is_ascii_digit() => {
+                while i < chars.len() && chars[i].is_ascii_digit() {i += 1;}
+                let s: String = chars[start..i].iter().collect();
+                match s.parse::<usize>() {
+                    Ok(n) => {output.push(Token::Number(n));}
+                    Err(_) => {return Err(errors::DbError::BadInput(format!("Number {} is too large", s)));}
+                }
+            }
+            _ if is_word(c) => {
+                while i < chars.len() && is_word(chars[i]) {i += 1;}
+                output.push(Token::Identifier(chars[start..i].iter().collect()));
+            }
+            _ => {
+                return Err(errors::DbError::BadInput(format!("Unexpected character {} at position {}", c, start)));
+            }
+        }
+    }
+    Ok(output)
+}
+
+
+#[derive(Clone, PartialEq)]
+pub enum PathElement {
+    Attribute(String),
+    Index(usize),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Path(pub Vec<PathElement>);
+
+impl Path {
+    pub fn resolve<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        let mut current = document;
+        for element in &self.0 {
+            current = match element {
+                PathElement::Attribute(name) => {current.as_object()?.get(name)?}
+                PathElement::Index(index) => {current.as_array()?.get(*index)?}
+            };
+        }
+        Some(current)
+    }
+}
+
+
+pub enum Operand {
+    Path(Path),
+    Value(Value),
+    Size(Path),
+}
+
+impl Operand {
+    fn evaluate<'a>(&'a self, document: &'a Value) -> Option<Cow<'a, Value>> {
+        match self {
+            Operand::Path(path) => {path.resolve(document).map(Cow::Borrowed)}
+            Operand::Value(value) => {Some(Cow::Borrowed(value))}
+            Operand::Size(path) => {
+                let size = match path.resolve(document)? {
+                    Value::String(s) => {s.chars().count()}
+                    Value::Array(a) => {a.len()}
+                    Value::Object(o) => {o.len()}
+                    _ => {return None;}
+                };
+                Some(Cow::Owned(Value::from(size)))
+            }
+        }
+    }
+}
+
+pub enum Comparator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+pub enum Condition {
+    Compare(Operand, Comparator, Operand),
+    Between(Operand, Operand, Operand),
+    In(Operand, Vec<Operand>),
+    AttributeExists(Path),
+    AttributeNotExists(Path),
+    AttributeType(Path, Operand),
+    BeginsWith(Operand, Operand),
+    Contains(Operand, Operand),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+// numbers compare by value, strings lexicographically, anything else only by equality
+pub fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            if let (Some(x), Some(y)) = (x.as_i64(), y.as_i64()) {
+                Some(x.cmp(&y))
+            } else if let (Some(x), Some(y)) = (x.as_u64(), y.as_u64()) {
+                Some(x.cmp(&y))
+            } else {
+                x.as_f64()?.partial_cmp(&y.as_f64()?)
+            }
+        }
+        (Value::String(x), Value::String(y)) => {Some(x.cmp(y))}
+        _ => {if a == b {Some(Ordering::Equal)} else {None}}
+    }
+}
+
+fn type_name(value: &Value) -> &str {
+    match value {
+        Value::Null => {"NULL"}
+        Value::Bool(_) => {"BOOL"}
+        Value::Number(_) => {"N"}
+        Value::String(_) => {"S"}
+        Value::Array(_) => {"L"}
+        Value::Object(_) => {"M"}
+    }
+}
+
+impl Condition {
+    pub fn evaluate(&self, document: &Value) -> bool {
+        match self {
+            Condition::Compare(a, comparator, b) => {
+                let (Some(a), Some(b)) = (a.evaluate(document), b.evaluate(document)) else {return false;};
+                let ordering = compare_values(&a, &b);
+                match comparator {
+                    Comparator::Equal => {ordering == Some(Ordering::Equal)}
+                    Comparator::NotEqual => {ordering != Some(Ordering::Equal)}
+                    Comparator::Less => {ordering == Some(Ordering::Less)}
+                    Comparator::LessEqual => {matches!(ordering, Some(Ordering::Less | Ordering::Equal))}
+                    Comparator::Greater => {ordering == Some(Ordering::Greater)}
+                    Comparator::GreaterEqual => {matches!(ordering, Some(Ordering::Greater | Ordering::Equal))}
+                }
+            }
+            Condition::Between(a, low, high) => {
+                let (Some(a), Some(low), Some(high)) = (a.evaluate(document), low.evaluate(document), high.evaluate(document)) else {return false;};
+                matches!(compare_values(&a, &low), Some(Ordering::Greater | Ordering::Equal))
+                    && matches!(compare_values(&a, &high), Some(Ordering::Less | Ordering::Equal))
+            }
+            Condition::In(a, candidates) => {
+                let Some(a) = a.evaluate(document) else {return false;};
+                candidates.iter().any(|c| {
+                    c.evaluate(document).is_some_and(|c| compare_values(&a, &c) == Some(Ordering::Equal))
+                })
+            }
+            Condition::AttributeExists(path) => {path.resolve(document).is_some()}
+            Condition::AttributeNotExists(path) => {path.resolve(document).is_none()}
+            Condition::AttributeType(path, t) => {
+                match (path.resolve(document), t.evaluate(document)) {
+                    (Some(value), Some(t)) => {t.as_str() == Some(type_name(value))}
+                    _ => {false}
+                }
+            }
+            Condition::BeginsWith(a, prefix) => {
+                match (a.evaluate(document), prefix.evaluate(document)) {
+                    (Some(a), Some(prefix)) => {
+                        match (a.as_str(), prefix.as_str()) {
+                            (Some(a), Some(prefix)) => {a.starts_with(prefix)}
+                            _ => {false}
+                        }
+                    }
+                    _ => {false}
+                }
+            }
+            Condition::Contains(a, needle) => {
+                match (a.evaluate(document), needle.evaluate(document)) {
+                    (Some(a), Some(needle)) => {
+                        match (a.as_ref(), needle.as_ref()) {
+                            (Value::String(a), Value::String(needle)) => {a.contains(needle.as_str())}
+                            (Value::Array(a), needle) => {
+                                a.iter().any(|x| compare_values(x, needle) == Some(Ordering::Equal))
+                            }
+                            _ => {false}
+                        }
+                    }
+                    _ => {false}
+                }
+            }
+            Condition::And(a, b) => {a.evaluate(document) && b.evaluate(document)}
+            Condition::Or(a, b) => {a.evaluate(document) || b.evaluate(document)}
+            Condition::Not(a) => {!a.evaluate(document)}
+        }
+    }
+}
+
+
+pub struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    names: Option<&'a serde_json::Map<String, Value>>,
+    values: Option<&'a serde_json::Map<String, Value>>,
+}
+
+impl<'a> Parser<'a> {
+    // placeholders are looked up in the expression_names and expression_values fields of the request
+    pub fn new(text: &str, request: &'a Value) -> Result<Self, errors::DbError> {
+        let names = match request.get("expression_names") {
+            None | Some(Value::Null) => {None}
+            Some(Value::Object(o)) => {Some(o)}
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("expression_names must be a valid JSON object")));}
+        };
+        let values = match request.get("expression_values") {
+            None | Some(Value::Null) => {None}
+            Some(Value::Object(o)) => {Some(o)}
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("expression_values must be a valid JSON object")));}
+        };
+        Ok(Self {
+            tokens: tokenize(text)?,
+            position: 0,
+            names,
+            values,
+        })
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    pub fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(s)) if s.eq_ignore_ascii_case(keyword))
+    }
+
+    pub fn next(&mut self) -> Option<Token> {
+        let output = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        output
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    pub fn expect(&mut self, token: Token, description: &str) -> Result<(), errors::DbError> {
+        if self.peek() == Some(&token) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(errors::DbError::BadInput(format!("Expected {} in expression", description)))
+        }
+    }
+
+    pub fn expect_end(&self) -> Result<(), errors::DbError> {
+        if self.is_done() {
+            Ok(())
+        } else {
+            Err(errors::DbError::BadInput(String::from("Unexpected trailing tokens in expression")))
+        }
+    }
+
+    fn resolve_name(&self, name: &str) -> Result<String, errors::DbError> {
+        match self.names.and_then(|n| n.get(name)).and_then(|n| n.as_str()) {
+            Some(s) => {Ok(String::from(s))}
+            None => {Err(errors::DbError::BadInput(format!("{} is not defined in expression_names", name)))}
+        }
+    }
+
+    pub fn resolve_value(&self, name: &str) -> Result<Value, errors::DbError> {
+        match self.values.and_then(|v| v.get(name)) {
+            Some(v) => {Ok(v.clone())}
+            None => {Err(errors::DbError::BadInput(format!("{} is not defined in expression_values", name)))}
+        }
+    }
+
+    fn parse_attribute_name(&mut self) -> Result<String, errors::DbError> {
+        match self.next() {
+            Some(Token::Identifier(s)) => {Ok(s)}
+            Some(Token::Name(s)) => {self.resolve_name(&s)}
+            _ => {Err(errors::DbError::BadInput(String::from("Expected an attribute name in expression")))}
+        }
+    }
+
+    pub fn parse_path(&mut self) -> Result<Path, errors::DbError> {
+        let mut elements = vec![PathElement::Attribute(self.parse_attribute_name()?)];
+        loop {
+            match self.peek() {
+                Some(Token::Dot) => {
+                    self.position += 1;
+                    elements.push(PathElement::Attribute(self.parse_attribute_name()?));
+                }
+                Some(Token::LeftBracket) => {
+                    self.position += 1;
+                    match self.next() {
+                        Some(Token::Number(n)) => {elements.push(PathElement::Index(n));}
+                        _ => {return Err(errors::DbError::BadInput(String::from("Expected a list index in expression")));}
+                    }
+                    self.expect(Token::RightBracket, "]")?;
+                }
+                _ => {break;}
+            }
+        }
+        Ok(Path(elements))
+    }
+
+    pub fn is_function_call(&self, name: &str) -> bool {
+        self.peek_keyword(name) && self.tokens.get(self.position + 1) == Some(&Token::LeftParen)
+    }
+
+    pub fn parse_operand(&mut self) -> Result<Operand, errors::DbError> {
+        if self.is_function_call("size") {
+            self.position += 2;
+            let path = self.parse_path()?;
+            self.expect(Token::RightParen, ")")?;
+            return Ok(Operand::Size(path));
+        }
+        match self.peek() {
+            Some(Token::Value(s)) => {
+                let s = s.clone();
+                self.position += 1;
+                Ok(Operand::Value(self.resolve_value(&s)?))
+            }
+            _ => {Ok(Operand::Path(self.parse_path()?))}
+        }
+    }
+
+    pub fn parse_condition(&mut self) -> Result<Condition, errors::DbError> {
+        let mut output = self.parse_conjunction()?;
+        while self.peek_keyword("OR") {
+            self.position += 1;
+            output = Condition::Or(Box::new(output), Box::new(self.parse_conjunction()?));
+        }
+        Ok(output)
+    }
+
+    fn parse_conjunction(&mut self) -> Result<Condition, errors::DbError> {
+        let mut output = self.parse_negation()?;
+        while self.peek_keyword("AND") {
+            self.position += 1;
+            output = Condition::And(Box::new(output), Box::new(self.parse_negation()?));
+        }
+        Ok(output)
+    }
+
+    fn parse_negation(&mut self) -> Result<Condition, errors::DbError> {
+        if self.peek_keyword("NOT") {
+            self.position += 1;
+            return Ok(Condition::Not(Box::new(self.parse_negation()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Condition, errors::DbError> {
+        if self.peek() == Some(&Token::LeftParen) {
+            self.position += 1;
+            let output = self.parse_condition()?;
+            self.expect(Token::RightParen, ")")?;
+            return Ok(output);
+        }
+        for function in ["attribute_exists", "attribute_not_exists", "attribute_type", "begins_with", "contains"] {
+            if self.is_function_call(function) {
+                self.position += 2;
+                return self.parse_function(function);
+            }
+        }
+        let operand = self.parse_operand()?;
+        if self.peek_keyword("BETWEEN") {
+            self.position += 1;
+            let low = self.parse_operand()?;
+            if !self.peek_keyword("AND") {
+                return Err(errors::DbError::BadInput(String::from("Expected AND in BETWEEN expression")));
+            }
+            self.position += 1;
+            let high = self.parse_operand()?;
+            return Ok(Condition::Between(operand, low, high));
+        }
+        if self.peek_keyword("IN") {
+            self.position += 1;
+            self.expect(Token::LeftParen, "(")?;
+            let mut candidates = vec![self.parse_operand()?];
+            while self.peek() == Some(&Token::Comma) {
+                self.position += 1;
+                candidates.push(self.parse_operand()?);
+            }
+            self.expect(Token::RightParen, ")")?;
+            return Ok(Condition::In(operand, candidates));
+        }
+        let comparator = match self.next() {
+            Some(Token::Equal) => {Comparator::Equal}
+            Some(Token::NotEqual) => {Comparator::NotEqual}
+            Some(Token::Less) => {Comparator::Less}
+            Some(Token::LessEqual) => {Comparator::LessEqual}
+            Some(Token::Greater) => {Comparator::Greater}
+            Some(Token::GreaterEqual) => {Comparator::GreaterEqual}
+            _ => {return Err(errors::DbError::BadInput(String::from("Expected a comparison in expression")));}
+        };
+        Ok(Condition::Compare(operand, comparator, self.parse_operand()?))
+    }
+
+    fn parse_function(&mut self, function: &str) -> Result<Condition, errors::DbError> {
+        let output = match function {
+            "attribute_exists" => {Condition::AttributeExists(self.parse_path()?)}
+            "attribute_not_exists" => {Condition::AttributeNotExists(self.parse_path()?)}
+            _ => {
+                let first = self.parse_operand()?;
+                self.expect(Token::Comma, ",")?;
+                let second = self.parse_operand()?;
+                match (function, first) {
+                    ("attribute_type", Operand::Path(path)) => {Condition::AttributeType(path, second)}
+                    ("attribute_type", _) => {return Err(errors::DbError::BadInput(String::from("attribute_type requires an attribute path")));}
+                    ("begins_with", first) => {Condition::BeginsWith(first, second)}
+                    (_, first) => {Condition::Contains(first, second)}
+                }
+            }
+        };
+        self.expect(Token::RightParen, ")")?;
+        Ok(output)
+    }
+}
+
+pub fn parse_condition(text: &str, request: &Value) -> Result<Condition, errors::DbError> {
+    let mut parser = Parser::new(text, request)?;
+    let output = parser.parse_condition()?;
+    parser.expect_end()?;
+    Ok(output)
+}
+
+// reads an optional expression string field off a request and parses it as a condition
+pub fn condition_field(request: &Value, field: &str) -> Result<Option<Condition>, errors::DbError> {
+    match request.get(field) {
+        None | Some(Value::Null) => {Ok(None)}
+        Some(Value::String(s)) => {Ok(Some(parse_condition(s, request)?))}
+        Some(_) => {Err(errors::DbError::BadInput(format!("{} must be a string", field)))}
+    }
+}
+
+// the attribute paths a read returns; everything else in the document is left out
+pub struct ProjectionExpression {
+    paths: Vec<Path>,
+}
+
+// copies only the requested paths; list elements keep their relative order but are packed together
+fn project(document: &Value, paths: &[&[PathElement]]) -> Option<Value> {
+    if paths.iter().any(|p| p.is_empty()) {
+        return Some(document.clone());
+    }
+    let rest = |first: &PathElement| -> Vec<&[PathElement]> {
+        paths.iter().filter(|p| p[0] == *first).map(|p| &p[1..]).collect()
+    };
+    match document {
+        Value::Object(o) => {
+            let mut output = serde_json::Map::new();
+            for path in paths {
+                if let PathElement::Attribute(name) = &path[0]
+                    && !output.contains_key(name)
+                    && let Some(child) = o.get(name)
+                    && let Some(value) = project(child, &rest(&path[0])) {
+                    output.insert(name.clone(), value);
+                }
+            }
+            if output.is_empty() {None} else {Some(Value::Object(output))}
+        }
+        Value::Array(a) => {
+            let mut indexes: Vec<usize> = paths.iter().filter_map(|p| match p[0] {
+                PathElement::Index(index) => {Some(index)}
+                _ => {None}
+            }).collect();
+            indexes.sort();
+            indexes.dedup();
+            let output: Vec<Value> = indexes.into_iter()
+                .filter_map(|index| project(a.get(index)?, &rest(&PathElement::Index(index))))
+                .collect();
+            if output.is_empty() {None} else {Some(Value::Array(output))}
+        }
+        _ => {None}
+    }
+}
+
+impl ProjectionExpression {
+    // the projection field is either one comma separated expression or a list of single paths
+    pub fn from_json(request: &Value) -> Result<Option<Self>, errors::DbError> {
+        let texts: Vec<&str> = match request.get("projection") {
+            None | Some(Value::Null) => {return Ok(None);}
+            Some(Value::String(s)) => {vec![s.as_str()]}
+            Some(Value::Array(a)) => {
+                let mut output = Vec::new();
+                for path in a {
+                    match path.as_str() {
+                        Some(s) => {output.push(s);}
+                        None => {return Err(errors::DbError::BadInput(String::from("projection paths must be strings")));}
+                    }
+                }
+                output
+            }
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("projection must be a string or an array of paths")));}
+        };
+        let mut paths = Vec::new();
+        for text in texts {
+            let mut parser = Parser::new(text, request)?;
+            loop {
+                paths.push(parser.parse_path()?);
+                if parser.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                parser.next();
+            }
+            parser.expect_end()?;
+        }
+        if paths.is_empty() {
+            return Err(errors::DbError::BadInput(String::from("projection must name at least one path")));
+        }
+        Ok(Some(Self {paths}))
+    }
+
+    pub fn apply(&self, document: &Value) -> Value {
+        let paths: Vec<&[PathElement]> = self.paths.iter().map(|p| p.0.as_slice()).collect();
+        project(document, &paths).unwrap_or_else(|| Value::Object(serde_json::Map::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn evaluate(text: &str, request: &Value, document: &Value) -> bool {
+        parse_condition(text, request).unwrap().evaluate(document)
+    }
+
+    // every combination of a, b and c being 1 or not
+    fn documents() -> Vec<(bool, bool, bool, Value)> {
+        let mut output = Vec::new();
+        for i in 0..8 {
+            let (a, b, c) = (i & 4 != 0, i & 2 != 0, i & 1 != 0);
+            output.push((a, b, c, json!({"a": a as i64, "b": b as i64, "c": c as i64})));
+        }
+        output
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        let request = json!({"expression_values": {":one": 1}});
+        for (a, b, c, document) in documents() {
+            assert_eq!(evaluate("NOT a = :one AND b = :one OR c = :one", &request, &document), (!a && b) || c);
+            assert_eq!(evaluate("a = :one OR b = :one AND c = :one", &request, &document), a || (b && c));
+            assert_eq!(evaluate("NOT (a = :one OR b = :one) AND c = :one", &request, &document), !(a || b) && c);
+            assert_eq!(evaluate("NOT NOT a = :one", &request, &document), a);
+        }
+    }
+
+    #[test]
+    fn between_takes_its_own_and_inside_an_and_chain() {
+        let request = json!({"expression_values": {":low": 1, ":high": 5, ":s": "x"}});
+        let text = "s = :s AND n BETWEEN :low AND :high AND attribute_exists(n)";
+        assert!(evaluate(text, &request, &json!({"n": 3, "s": "x"})));
+        assert!(evaluate(text, &request, &json!({"n": 5, "s": "x"})));
+        assert!(!evaluate(text, &request, &json!({"n": 6, "s": "x"})));
+        assert!(!evaluate(text, &request, &json!({"n": 3, "s": "y"})));
+        assert!(parse_condition("n BETWEEN :low", &request).is_err());
+    }
+
+    #[test]
+    fn looks_up_names_and_values() {
+        let request = json!({
+            "expression_names": {"#dotted": "a.b", "#list": "items", "#size": "size"},
+            "expression_values": {":v": {"k": [1, 2]}, ":two": 2},
+        });
+        let document = json!({"a.b": {"k": [1, 2]}, "items": [{"size": 1}, {"size": 2}]});
+        // a name stands for one whole attribute, dots and keywords included
+        assert!(evaluate("#dotted = :v", &request, &document));
+        assert!(evaluate("#list[1].#size = :two", &request, &document));
+        assert!(!evaluate("#list[0].#size = :two", &request, &document));
+        assert!(matches!(parse_condition("#missing = :v", &request), Err(errors::DbError::BadInput(_))));
+        assert!(matches!(parse_condition("#dotted = :missing", &request), Err(errors::DbError::BadInput(_))));
+        assert!(matches!(parse_condition("a = :v", &json!({"expression_values": [1]})), Err(errors::DbError::BadInput(_))));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::*;
+
+// index sort key, then the base table's primary and sort key so documents sharing an index key stay distinct
+type IndexPosition = (db_keys::KeyDatatype, db_keys::KeyDatatype, db_keys::KeyDatatype);
+
+pub enum Projection {
+    All,
+    KeysOnly,
+    Include(Vec<String>),
+}
+
+impl Projection {
+    fn from_json(info: Option<&Value>) -> Result<Self, errors::DbError> {
+        match info {
+            None | Some(Value::Null) => {Ok(Self::All)}
+            Some(Value::String(s)) if s.eq("ALL") => {Ok(Self::All)}
+            Some(Value::String(s)) if s.eq("KEYS_ONLY") => {Ok(Self::KeysOnly)}
+            Some(Value::Array(attributes)) => {
+                let mut output = Vec::new();
+                for attribute in attributes {
+                    match attribute.as_str() {
+                        Some(s) => {output.push(String::from(s));}
+                        None => {return Err(errors::DbError::BadInput(String::from("projection attributes must be strings")));}
+                    }
+                }
+                Ok(Self::Include(output))
+            }
+            Some(_) => {Err(errors::DbError::BadInput(String::from("projection must be ALL, KEYS_ONLY or an array of attribute names")))}
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Projection::All => {Value::from("ALL")}
+            Projection::KeysOnly => {Value::from("KEYS_ONLY")}
+            Projection::Include(attributes) => {serde_json::json!(attributes)}
+        }
+    }
+
+    // key attributes are always carried over, whatever the projection
+    pub fn apply(&self, keys: &[&db_keys::Key], document: &Value) -> Value {
+        let attributes: Vec<&str> = match self {
+            Projection::All => {return document.clone();}
+            Projection::KeysOnly => {Vec::new()}
+            Projection::Include(attributes) => {attributes.iter().map(|s| s.as_str()).collect()}
+        };
+        let mut output = serde_json::Map::new();
+        for key in keys {
+            key.copy_attributes(document, &mut output);
+        }
+        for name in attributes {
+            if let Some(value) = document.get(name) {
+                output.insert(String::from(name), value.clone());
+            }
+        }
+        Value::Object(output)
+    }
+}
+
+pub struct GlobalIndex {
+    pub index_name: String,
+    primary_key: db_keys::Key,
+    sort_key: db_keys::Key,
+    projection: Projection,
+    data: HashMap<db_keys::KeyDatatype, BTreeMap<IndexPosition, IndexEntry>>,
+}
+
+// the expiry is kept beside the projected document, which may not include the table's ttl attribute
+struct IndexEntry {
+    document: Value,
+    expires_at: Option<f64>,
+}
+
+fn parse_key(info: &Value, field: &str) -> Result<db_keys::Key, errors::DbError> {
+    db_keys::Key::from_json(info.get(field).unwrap())
+}
+
+impl GlobalIndex {
+    pub fn new(info: &Value) -> Result<Self, errors::DbError> {
+        if !info.is_object() {
+            return Err(errors::DbError::BadInput(String::from("Index definition must be a valid JSON object")));
+        }
+        validation::check_string_fields_exist(info, &["index_name"])?;
+        validation::check_key_fields_exist(info, &["primary_key"])?;
+        let sort_key = match info.get("sort_key") {
+            None | Some(Value::Null) => {
+                db_keys::Key {
+                    name: String::from("Null"),
+                    datatype: db_keys::KeyDatatype::Null(()),
+                    path: vec![String::from("Null")],
+                    components: Vec::new(),
+                }
+            }
+            Some(_) => {
+                validation::check_key_fields_exist(info, &["sort_key"])?;
+                parse_key(info, "sort_key")?
+            }
+        };
+        Ok(Self {
+            index_name: info.get("index_name").unwrap().as_str().unwrap().to_string(),
+            primary_key: parse_key(info, "primary_key")?,
+            sort_key,
+            projection: Projection::from_json(info.get("projection"))?,
+            data: HashMap::new(),
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "index_name": self.index_name,
+            "primary_key": self.primary_key.to_json(),
+            "sort_key": self.sort_key.to_json(),
+            "projection": self.projection.to_json(),
+        })
+    }
+
+    pub fn item_count(&self) -> usize {
+        self.data.values().map(|partition| partition.len()).sum()
+    }
+
+    // documents missing either index key are left out of the index
+    fn index_keys(&self, document: &Value) -> Option<(db_keys::KeyDatatype, db_keys::KeyDatatype)> {
+        let pk = db_keys::extract_key(&self.primary_key, document).ok()?;
+        let sk = db_keys::extract_key(&self.sort_key, document).ok()?;
+        Some((pk, sk))
+    }
+
+    pub fn insert(&mut self, table_keys: [&db_keys::Key; 2], table_pk: &db_keys::KeyDatatype, table_sk: &db_keys::KeyDatatype, document: &Value, expires_at: Option<f64>) {
+        if let Some((pk, sk)) = self.index_keys(document) {
+            let projected = self.projection.apply(&[table_keys[0], table_keys[1], &self.primary_key, &self.sort_key], document);
+            self.data.entry(pk).or_default().insert((sk, table_pk.clone(), table_sk.clone()), IndexEntry {
+                document: projected,
+                expires_at,
+            });
+        }
+    }
+
+    pub fn remove(&mut self, table_pk: &db_keys::KeyDatatype, table_sk: &db_keys::KeyDatatype, document: &Value) {
+        if let Some((pk, sk)) = self.index_keys(document)
+            && let Some(partition) = self.data.get_mut(&pk) {
+            partition.remove(&(sk, table_pk.clone(), table_sk.clone()));
+            if partition.is_empty() {
+                self.data.remove(&pk);
+            }
+        }
+    }
+
+    pub fn query(&self, table_keys: [&db_keys::Key; 2], info: &Value, now: f64) -> Result<Value, errors::DbError> {
+        let pk = db_keys::extract_key(&self.primary_key, info)?;
+        let query = query::Query::from_json(&self.sort_key, info)?;
+        let start = match pagination::start_key(info)? {
+            None => {None}
+            Some(position) => {Some(self.decode_position(table_keys, &pk, &position)?)}
+        };
+        let (output, last) = match self.data.get(&pk) {
+            None => {(Vec::new(), None)}
+            Some(partition) => {
+                let entries = partition.iter()
+                    .filter(|(_, entry)| entry.expires_at.is_none_or(|t| t > now))
+                    .map(|(position, entry)| (position, &entry.document));
+                query.run(entries, |position| &position.0, start.as_ref())
+            }
+        };
+        Ok(serde_json::json!({
+            "count": output.len(),
+            "data": Value::Array(output),
+            "last_evaluated_key": last.map(|position| self.encode_position(&pk, &position)),
+        }))
+    }
+
+    fn encode_position(&self, pk: &db_keys::KeyDatatype, position: &IndexPosition) -> String {
+        pagination::encode_token(&serde_json::json!({
+            "pk": pk.to_value(),
+            "sk": position.0.to_value(),
+            "table_pk": position.1.to_value(),
+            "table_sk": position.2.to_value(),
+        }))
+    }
+
+    fn decode_position(&self, table_keys: [&db_keys::Key; 2], pk: &db_keys::KeyDatatype, position: &Value) -> Result<IndexPosition, errors::DbError> {
+        let invalid = || errors::DbError::BadInput(String::from("Continuation token does not belong to this index"));
+        let field = |name: &str| position.get(name).ok_or_else(invalid);
+        let start_pk = db_keys::parse_value(&self.primary_key, field("pk")?).map_err(|_| invalid())?;
+        if start_pk != *pk {
+            return Err(errors::DbError::BadInput(String::from("Continuation token belongs to a different partition")));
+        }
+        Ok((
+            db_keys::parse_value(&self.sort_key, field("sk")?).map_err(|_| invalid())?,
+            db_keys::parse_value(table_keys[0], field("table_pk")?).map_err(|_| invalid())?,
+            db_keys::parse_value(table_keys[1], field("table_sk")?).map_err(|_| invalid())?,
+        ))
+    }
+}
+
+
+// shares the table's primary key, so its ordering lives next to the documents in each table::Partition
+pub struct LocalIndex {
+    pub index_name: String,
+    sort_key: db_keys::Key,
+    projection: Projection,
+}
+
+impl LocalIndex {
+    pub fn new(info: &Value) -> Result<Self, errors::DbError> {
+        if !info.is_object() {
+            return Err(errors::DbError::BadInput(String::from("Index definition must be a valid JSON object")));
+        }
+        validation::check_string_fields_exist(info, &["index_name"])?;
+        validation::check_key_fields_exist(info, &["sort_key"])?;
+        Ok(Self {
+            index_name: info.get("index_name").unwrap().as_str().unwrap().to_string(),
+            sort_key: parse_key(info, "sort_key")?,
+            projection: Projection::from_json(info.get("projection"))?,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "index_name": self.index_name,
+            "sort_key": self.sort_key.to_json(),
+            "projection": self.projection.to_json(),
+        })
+    }
+
+    // documents missing the index sort key are left out of the index
+    pub fn sort_value(&self, document: &Value) -> Option<db_keys::KeyDatatype> {
+        db_keys::extract_key(&self.sort_key, document).ok()
+    }
+
+    pub fn query(
+        &self,
+        table_keys: [&db_keys::Key; 2],
+        pk: &db_keys::KeyDatatype,
+        partition: Option<&table::Partition>,
+        info: &Value,
+        is_live: impl Fn(&Value) -> bool,
+    ) -> Result<Value, errors::DbError> {
+        let query = query::Query::from_json(&self.sort_key, info)?;
+        let start = match pagination::start_key(info)? {
+            None => {None}
+            Some(position) => {Some(self.decode_position(table_keys, pk, &position)?)}
+        };
+        let ordering = partition.and_then(|p| p.local_indexes.get(&self.index_name));
+        let (output, last) = match (partition, ordering) {
+            (Some(partition), Some(ordering)) => {
+                let entries = ordering.iter()
+                    .map(|position| (position, partition.documents.get(&position.1).unwrap()))
+                    .filter(|(_, document)| is_live(document));
+                query.run(entries, |position| &position.0, start.as_ref())
+            }
+            _ => {(Vec::new(), None)}
+        };
+        let output = match self.projection {
+            Projection::All => {output}
+            _ => {output.iter().map(|document| self.projection.apply(&[table_keys[0], table_keys[1], &self.sort_key], document)).collect()}
+        };
+        Ok(serde_json::json!({
+            "count": output.len(),
+            "data": Value::Array(output),
+            "last_evaluated_key": last.map(|position| self.encode_position(pk, &position)),
+        }))
+    }
+
+    fn encode_position(&self, pk: &db_keys::KeyDatatype, position: &(db_keys::KeyDatatype, db_keys::KeyDatatype)) -> String {
+        pagination::encode_token(&serde_json::json!({
+            "pk": pk.to_value(),
+            "sk": position.0.to_value(),
+            "table_sk": position.1.to_value(),
+        }))
+    }
+
+    fn decode_position(&self, table_keys: [&db_keys::Key; 2], pk: &db_keys::KeyDatatype, position: &Value) -> Result<(db_keys::KeyDatatype, db_keys::KeyDatatype), errors::DbError> {
+        let invalid = || errors::DbError::BadInput(String::from("Continuation token does not belong to this index"));
+        let field = |name: &str| position.get(name).ok_or_else(invalid);
+        let start_pk = db_keys::parse_value(table_keys[0], field("pk")?).map_err(|_| invalid())?;
+        if start_pk != *pk {
+            return Err(errors::DbError::BadInput(String::from("Continuation token belongs to a different partition")));
+        }
+        Ok((
+            db_keys::parse_value(&self.sort_key, field("sk")?).map_err(|_| invalid())?,
+            db_keys::parse_value(table_keys[1], field("table_sk")?).map_err(|_| invalid())?,
+        ))
+    }
+}
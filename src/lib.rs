@@ -17,3 +17,15 @@ pub mod errors;
 mod validation;
 mod table;
 mod db_keys;
+mod query;
+mod expressions;
+mod pagination;
+mod index;
+mod updates;
+mod ttl;
+mod stream;
+mod subscriptions;
+mod snapshot;
+mod backups;
+pub mod config;
+pub mod wal;
@@ -0,0 +1,46 @@
+use crate::*;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+// tokens are hex encoded JSON so clients treat them as opaque strings
+pub fn encode_token(position: &Value) -> String {
+    let bytes = serde_json::to_vec(position).unwrap();
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        output.push(HEX_DIGITS[(b >> 4) as usize] as char);
+        output.push(HEX_DIGITS[(b & 0xf) as usize] as char);
+    }
+    output
+}
+
+pub fn decode_token(token: &str) -> Result<Value, errors::DbError> {
+    let invalid = || errors::DbError::BadInput(String::from("Invalid continuation token"));
+    if !token.len().is_multiple_of(2) || !token.is_ascii() {
+        return Err(invalid());
+    }
+    let mut bytes = Vec::with_capacity(token.len() / 2);
+    for i in (0..token.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&token[i..i + 2], 16).map_err(|_| invalid())?);
+    }
+    serde_json::from_slice(&bytes).map_err(|_| invalid())
+}
+
+pub fn limit(info: &Value) -> Result<Option<usize>, errors::DbError> {
+    match info.get("limit") {
+        None | Some(Value::Null) => {Ok(None)}
+        Some(l) => {
+            match l.as_u64() {
+                Some(n) if n > 0 => {Ok(Some(n as usize))}
+                _ => {Err(errors::DbError::BadInput(String::from("limit must be a positive integer")))}
+            }
+        }
+    }
+}
+
+pub fn start_key(info: &Value) -> Result<Option<Value>, errors::DbError> {
+    match info.get("exclusive_start_key") {
+        None | Some(Value::Null) => {Ok(None)}
+        Some(Value::String(token)) => {Ok(Some(decode_token(token)?))}
+        Some(_) => {Err(errors::DbError::BadInput(String::from("exclusive_start_key must be a continuation token string")))}
+    }
+}
@@ -0,0 +1,160 @@
+use std::ops::Bound;
+use crate::*;
+
+pub enum SortKeyCondition {
+    Equal(db_keys::KeyDatatype),
+    LessThan(db_keys::KeyDatatype),
+    LessThanOrEqual(db_keys::KeyDatatype),
+    GreaterThan(db_keys::KeyDatatype),
+    GreaterThanOrEqual(db_keys::KeyDatatype),
+    Between(db_keys::KeyDatatype, db_keys::KeyDatatype),
+    // the prefix is a String or Binary key
+    BeginsWith(db_keys::KeyDatatype),
+}
+
+impl SortKeyCondition {
+    pub fn from_json(key: &db_keys::Key, info: &Value) -> Result<Self, errors::DbError> {
+        validation::check_string_fields_exist(info, &["operator"])?;
+        let operator = info.get("operator").unwrap().as_str().unwrap();
+        if operator.eq("between") {
+            let values = info.get("values").and_then(|v| v.as_array());
+            if values.is_none() || values.unwrap().len() != 2 {
+                return Err(errors::DbError::BadInput(String::from("between requires values to be an array of exactly 2 elements")));
+            }
+            let values = values.unwrap();
+            let low = db_keys::parse_value(key, &values[0])?;
+            let high = db_keys::parse_value(key, &values[1])?;
+            if low > high {
+                return Err(errors::DbError::BadInput(String::from("between requires the lower value to come first")));
+            }
+            return Ok(Self::Between(low, high));
+        }
+        if info.get("value").is_none() {
+            return Err(errors::DbError::MissingFields(format!("value field is required for operator {}", operator)));
+        }
+        let value = info.get("value").unwrap();
+        if operator.eq("begins_with") {
+            return match &key.datatype {
+                db_keys::KeyDatatype::String(_) | db_keys::KeyDatatype::Binary(_) => {Ok(Self::BeginsWith(db_keys::parse_value(key, value)?))}
+                _ => {Err(errors::DbError::BadInput(String::from("begins_with is only supported on String and Binary sort keys")))}
+            };
+        }
+        let value = db_keys::parse_value(key, value)?;
+        match operator {
+            "=" => {Ok(Self::Equal(value))}
+            "<" => {Ok(Self::LessThan(value))}
+            "<=" => {Ok(Self::LessThanOrEqual(value))}
+            ">" => {Ok(Self::GreaterThan(value))}
+            ">=" => {Ok(Self::GreaterThanOrEqual(value))}
+            _ => {Err(errors::DbError::BadInput(format!("Unknown sort key operator {}", operator)))}
+        }
+    }
+
+    pub fn matches(&self, sk: &db_keys::KeyDatatype) -> bool {
+        match self {
+            SortKeyCondition::Equal(v) => {sk == v}
+            SortKeyCondition::LessThan(v) => {sk < v}
+            SortKeyCondition::LessThanOrEqual(v) => {sk <= v}
+            SortKeyCondition::GreaterThan(v) => {sk > v}
+            SortKeyCondition::GreaterThanOrEqual(v) => {sk >= v}
+            SortKeyCondition::Between(low, high) => {sk >= low && sk <= high}
+            SortKeyCondition::BeginsWith(prefix) => {
+                match (sk, prefix) {
+                    (db_keys::KeyDatatype::String(s), db_keys::KeyDatatype::String(prefix)) => {s.starts_with(prefix.as_str())}
+                    (db_keys::KeyDatatype::Binary(b), db_keys::KeyDatatype::Binary(prefix)) => {b.starts_with(prefix)}
+                    _ => {false}
+                }
+            }
+        }
+    }
+
+    // the narrowest BTreeMap range that can contain every match
+    pub fn bounds(&self) -> (Bound<db_keys::KeyDatatype>, Bound<db_keys::KeyDatatype>) {
+        match self {
+            SortKeyCondition::Equal(v) => {(Bound::Included(v.clone()), Bound::Included(v.clone()))}
+            SortKeyCondition::LessThan(v) => {(Bound::Unbounded, Bound::Excluded(v.clone()))}
+            SortKeyCondition::LessThanOrEqual(v) => {(Bound::Unbounded, Bound::Included(v.clone()))}
+            SortKeyCondition::GreaterThan(v) => {(Bound::Excluded(v.clone()), Bound::Unbounded)}
+            SortKeyCondition::GreaterThanOrEqual(v) => {(Bound::Included(v.clone()), Bound::Unbounded)}
+            SortKeyCondition::Between(low, high) => {(Bound::Included(low.clone()), Bound::Included(high.clone()))}
+            SortKeyCondition::BeginsWith(prefix) => {(Bound::Included(prefix.clone()), Bound::Unbounded)}
+        }
+    }
+}
+
+pub struct Query {
+    pub condition: Option<SortKeyCondition>,
+    pub ascending: bool,
+    pub limit: Option<usize>,
+    pub projection: Option<expressions::ProjectionExpression>,
+}
+
+impl Query {
+    pub fn from_json(sort_key: &db_keys::Key, info: &Value) -> Result<Self, errors::DbError> {
+        let condition = match info.get("sort_key_condition") {
+            None | Some(Value::Null) => {None}
+            Some(c) => {
+                if !c.is_object() {
+                    return Err(errors::DbError::BadInput(String::from("sort_key_condition must be a valid JSON object")));
+                }
+                Some(SortKeyCondition::from_json(sort_key, c)?)
+            }
+        };
+        let ascending = match info.get("ascending") {
+            None | Some(Value::Null) => {true}
+            Some(Value::Bool(b)) => {*b}
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("ascending must be a boolean")));}
+        };
+        let limit = pagination::limit(info)?;
+        Ok(Self {
+            condition,
+            ascending,
+            limit,
+            projection: expressions::ProjectionExpression::from_json(info)?,
+        })
+    }
+
+    pub fn bounds(&self) -> (Bound<db_keys::KeyDatatype>, Bound<db_keys::KeyDatatype>) {
+        match &self.condition {
+            None => {(Bound::Unbounded, Bound::Unbounded)}
+            Some(condition) => {condition.bounds()}
+        }
+    }
+
+    // returns the matching documents and, when the limit cut the page short, the position to resume after
+    pub fn run<'a, K: Ord + Clone + 'a>(
+        &self,
+        entries: impl DoubleEndedIterator<Item = (&'a K, &'a Value)>,
+        sort_key_of: impl Fn(&K) -> &db_keys::KeyDatatype,
+        start: Option<&K>,
+    ) -> (Vec<Value>, Option<K>) {
+        let matching = entries.filter(|(position, _)| {
+            self.condition.as_ref().is_none_or(|c| c.matches(sort_key_of(position)))
+        });
+        let mut matching: Box<dyn Iterator<Item = (&K, &Value)>> = match (self.ascending, start) {
+            (true, None) => {Box::new(matching)}
+            (true, Some(start)) => {Box::new(matching.skip_while(move |(position, _)| *position <= start))}
+            (false, None) => {Box::new(matching.rev())}
+            (false, Some(start)) => {Box::new(matching.rev().skip_while(move |(position, _)| *position >= start))}
+        };
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let mut output = Vec::new();
+        let mut last = None;
+        while output.len() < limit {
+            match matching.next() {
+                None => {return (output, None);}
+                Some((position, doc)) => {
+                    output.push(match &self.projection {
+                        None => {doc.clone()}
+                        Some(projection) => {projection.apply(doc)}
+                    });
+                    last = Some(position);
+                }
+            }
+        }
+        match matching.next() {
+            None => {(output, None)}
+            Some(_) => {(output, last.cloned())}
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::io::Write;
+use crate::*;
+
+// older versions kept every table in one snapshot, the newest first and then the one it replaced
+pub const LEGACY_FILES: [&str; 2] = ["data.json", "data.prev.json"];
+
+// lsns of a table's current file and of the previous one, which is kept as its fallback
+pub struct TableFiles {
+    pub saved_lsn: u64,
+    pub prev_lsn: u64,
+}
+
+// table names can hold anything, so everything but letters, digits, - and _ is percent encoded
+pub fn file_stem(table_name: &str) -> String {
+    let mut output = String::new();
+    for byte in table_name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            output.push(byte as char);
+        } else {
+            output.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    output
+}
+
+fn current_file(stem: &str) -> String {
+    format!("{}.json", stem)
+}
+
+fn prev_file(stem: &str) -> String {
+    format!("{}.prev.json", stem)
+}
+
+// a table's backups are appended to a file of their own rather than rewritten with the table
+pub fn backups_file(stem: &str) -> String {
+    format!("{}.backups.log", stem)
+}
+
+// the files to try for a table, newest first
+pub fn table_files(stem: &str) -> [String; 2] {
+    [current_file(stem), prev_file(stem)]
+}
+
+// every stem with a current, previous or backups file in dir; temporary and quarantined files end in neither
+pub fn stems(dir: &std::path::Path) -> Result<BTreeSet<String>, errors::DbError> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| errors::DbError::StorageError(format!("Could not list {}: {}", dir.display(), e)))?;
+    let mut output = BTreeSet::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let stem = file_name.strip_suffix(".prev.json")
+            .or_else(|| file_name.strip_suffix(".json"))
+            .or_else(|| file_name.strip_suffix(".backups.log"));
+        if let Some(stem) = stem {
+            output.insert(stem.to_string());
+        }
+    }
+    Ok(output)
+}
+
+// None when there is no file, an error describing the damage when there is one that can't be used;
+// snapshots are {"lsn": ..., "tables": [...]}, the oldest ones a bare list of tables
+pub async fn read(file_path: &std::path::Path) -> Result<Option<(u64, Vec<table::Table>)>, String> {
+    // file opening check
+    let mut file = match std::fs::File::open(file_path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {return Ok(None);}
+        Err(e) => {return Err(e.to_string());}
+        Ok(file) => {file}
+    };
+
+    // file read
+    let mut s = String::new();
+    file.read_to_string(&mut s).map_err(|e| e.to_string())?;
+    // what older versions left behind after starting without data
+    if s.is_empty() {
+        return Ok(None);
+    }
+
+    // JSON-ify check
+    let (lsn, tables) = match serde_json::from_str::<Value>(s.as_str()).map_err(|e| e.to_string())? {
+        Value::Array(tables) => {(0, tables)}
+        Value::Object(mut snapshot) => {
+            let Some(lsn) = snapshot.get("lsn").and_then(|l| l.as_u64()) else {
+                return Err(String::from("lsn is missing"));
+            };
+            let Some(Value::Array(tables)) = snapshot.remove("tables") else {
+                return Err(String::from("tables is missing"));
+            };
+            (lsn, tables)
+        }
+        _ => {return Err(String::from("not a snapshot"));}
+    };
+
+    // every table has to load, a snapshot missing some of them is no better than a corrupt one
+    let mut output = Vec::new();
+    for table in tables {
+        output.push(table::Table::new(&table).await.map_err(|e| e.to_string())?);
+    }
+    Ok(Some((lsn, output)))
+}
+
+// the lines of a backups file and whether its last one was torn by a crash partway through an append,
+// None when there is no file
+pub fn read_backups(file_path: &std::path::Path) -> Result<Option<(Vec<Value>, bool)>, String> {
+    let contents = match std::fs::read(file_path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {return Ok(None);}
+        Err(e) => {return Err(e.to_string());}
+        Ok(contents) => {contents}
+    };
+    let mut lines = Vec::new();
+    let mut torn = false;
+    let mut rest = contents.as_slice();
+    while !rest.is_empty() {
+        let (line, complete) = match rest.iter().position(|b| *b == b'\n') {
+            Some(end) => {(&rest[..end], true)}
+            None => {(rest, false)}
+        };
+        rest = &rest[(line.len() + 1).min(rest.len())..];
+        match serde_json::from_slice::<Value>(line) {
+            Ok(value) => {lines.push(value);}
+            Err(_) if !complete => {torn = true;}
+            Err(e) => {return Err(e.to_string());}
+        }
+    }
+    Ok(Some((lines, torn)))
+}
+
+// moves an unreadable file aside under a name it won't be looked for by, so later saves can't overwrite it
+pub fn quarantine(dir: &std::path::Path, file_name: &str, reason: &str, policy: &config::CorruptionPolicy) -> Result<(), errors::DbError> {
+    if let config::CorruptionPolicy::Refuse = policy {
+        return Err(errors::DbError::StorageError(format!("{} is corrupt ({}), refusing to start", file_name, reason)));
+    }
+    let target = format!("{}.corrupt-{}", file_name, ttl::now() as u64);
+    std::fs::rename(dir.join(file_name), dir.join(&target))
+        .map_err(|e| errors::DbError::StorageError(format!("Could not quarantine {}: {}", file_name, e)))?;
+    eprintln!("{} is corrupt ({}), moved it to {}", file_name, reason, target);
+    Ok(())
+}
+
+fn remove_if_exists(path: &std::path::Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {Err(e)}
+        _ => {Ok(())}
+    }
+}
+
+// the current file becomes the fallback and the table is written in its place;
+// a crash in between leaves only the fallback, which startup then loads
+pub fn write_table(dir: &std::path::Path, stem: &str, lsn: u64, definition: &Value) -> std::io::Result<()> {
+    match std::fs::rename(dir.join(current_file(stem)), dir.join(prev_file(stem))) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {return Err(e);}
+        _ => {}
+    }
+    let snapshot = serde_json::json!({
+        "lsn": lsn,
+        "tables": [definition],
+    });
+    wal::write_atomically(&dir.join(current_file(stem)), &serde_json::to_vec(&snapshot).unwrap())
+}
+
+// makes the fallback a copy of the current file, once the table has stopped changing
+pub fn refresh_prev(dir: &std::path::Path, stem: &str) -> std::io::Result<()> {
+    let contents = std::fs::read(dir.join(current_file(stem)))?;
+    wal::write_atomically(&dir.join(prev_file(stem)), &contents)
+}
+
+// rewrites the file, or appends to it; a failed append is cut back off, and if even that fails the
+// caller has the next save rewrite the file
+pub fn write_backups(dir: &std::path::Path, stem: &str, file: &backups::BackupsFile) -> std::io::Result<()> {
+    let path = dir.join(backups_file(stem));
+    if file.rewrite {
+        return wal::write_atomically(&path, &file.contents);
+    }
+    let mut f = std::fs::OpenOptions::new().append(true).open(&path)?;
+    let len = f.metadata()?.len();
+    if let Err(e) = f.write_all(&file.contents).and_then(|_| f.sync_data()) {
+        let _ = f.set_len(len);
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub fn remove_table(dir: &std::path::Path, stem: &str) -> std::io::Result<()> {
+    remove_if_exists(&dir.join(current_file(stem)))?;
+    remove_if_exists(&dir.join(prev_file(stem)))?;
+    remove_if_exists(&dir.join(backups_file(stem)))?;
+    std::fs::File::open(dir)?.sync_all()
+}
+
+pub fn remove_legacy(dir: &std::path::Path) -> std::io::Result<()> {
+    for file_name in LEGACY_FILES {
+        remove_if_exists(&dir.join(file_name))?;
+    }
+    std::fs::File::open(dir)?.sync_all()
+}
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use crate::*;
+
+// oldest records are dropped once the log grows past this, which moves the trim horizon forward
+const STREAM_RECORD_LIMIT: usize = 10000;
+const STREAM_READ_LIMIT: usize = 1000;
+
+pub enum ViewType {
+    KeysOnly,
+    NewImage,
+    OldImage,
+    NewAndOldImages,
+}
+
+impl ViewType {
+    fn from_json(info: Option<&Value>) -> Result<Self, errors::DbError> {
+        match info.and_then(|v| v.as_str()) {
+            None => {Err(errors::DbError::MissingFields(String::from("view_type field is missing or null")))}
+            Some("KEYS_ONLY") => {Ok(Self::KeysOnly)}
+            Some("NEW_IMAGE") => {Ok(Self::NewImage)}
+            Some("OLD_IMAGE") => {Ok(Self::OldImage)}
+            Some("NEW_AND_OLD_IMAGES") => {Ok(Self::NewAndOldImages)}
+            Some(_) => {Err(errors::DbError::BadInput(String::from("view_type must be KEYS_ONLY, NEW_IMAGE, OLD_IMAGE or NEW_AND_OLD_IMAGES")))}
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            ViewType::KeysOnly => {Value::from("KEYS_ONLY")}
+            ViewType::NewImage => {Value::from("NEW_IMAGE")}
+            ViewType::OldImage => {Value::from("OLD_IMAGE")}
+            ViewType::NewAndOldImages => {Value::from("NEW_AND_OLD_IMAGES")}
+        }
+    }
+}
+
+struct StreamLog {
+    next_sequence: u64,
+    records: VecDeque<Value>,
+}
+
+impl StreamLog {
+    fn first_sequence(&self) -> u64 {
+        self.next_sequence - self.records.len() as u64
+    }
+}
+
+// records only live in memory, so each run of the server starts a new stream with its own label
+pub struct Stream {
+    view_type: ViewType,
+    label: String,
+    log: RwLock<StreamLog>,
+}
+
+impl Stream {
+    pub fn from_json(info: Option<&Value>) -> Result<Option<Self>, errors::DbError> {
+        match info {
+            None | Some(Value::Null) => {Ok(None)}
+            Some(info) if info.is_object() => {
+                Ok(Some(Self {
+                    view_type: ViewType::from_json(info.get("view_type"))?,
+                    label: format!("{:.6}", ttl::now()),
+                    log: RwLock::new(StreamLog {
+                        next_sequence: 1,
+                        records: VecDeque::new(),
+                    }),
+                }))
+            }
+            Some(_) => {Err(errors::DbError::BadInput(String::from("stream must be a valid JSON object")))}
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "view_type": self.view_type.to_json(),
+        })
+    }
+
+    // must run while the partition holding the document is write locked, so records for a key keep write order
+    pub async fn record(&self, keys: Value, old: Option<&Value>, new: Option<&Value>) {
+        let event_name = match (old, new) {
+            (None, None) => {return;}
+            (None, Some(_)) => {"INSERT"}
+            (Some(_), Some(_)) => {"MODIFY"}
+            (Some(_), None) => {"REMOVE"}
+        };
+        let mut guard = self.log.write().await;
+        let mut record = serde_json::json!({
+            "sequence_number": guard.next_sequence,
+            "event_name": event_name,
+            "timestamp": ttl::now(),
+            "keys": keys,
+        });
+        if matches!(self.view_type, ViewType::OldImage | ViewType::NewAndOldImages) && let Some(old) = old {
+            record["old_image"] = old.clone();
+        }
+        if matches!(self.view_type, ViewType::NewImage | ViewType::NewAndOldImages) && let Some(new) = new {
+            record["new_image"] = new.clone();
+        }
+        guard.next_sequence += 1;
+        guard.records.push_back(record);
+        if guard.records.len() > STREAM_RECORD_LIMIT {
+            guard.records.pop_front();
+        }
+    }
+
+    fn encode_cursor(&self, sequence_number: u64) -> String {
+        pagination::encode_token(&serde_json::json!({
+            "label": self.label,
+            "sequence_number": sequence_number,
+        }))
+    }
+
+    // a cursor is the sequence number of the next record to read
+    fn decode_cursor(&self, info: &Value) -> Result<u64, errors::DbError> {
+        let cursor = match info.get("cursor") {
+            None | Some(Value::Null) => {return Err(errors::DbError::MissingFields(String::from("cursor field is missing or null")));}
+            Some(Value::String(cursor)) => {pagination::decode_token(cursor)?}
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("cursor must be a string")));}
+        };
+        if cursor.get("label").and_then(|l| l.as_str()) != Some(self.label.as_str()) {
+            return Err(errors::DbError::BadInput(String::from("Cursor does not belong to this stream")));
+        }
+        cursor.get("sequence_number").and_then(|s| s.as_u64())
+            .ok_or_else(|| errors::DbError::BadInput(String::from("Invalid cursor")))
+    }
+
+    pub async fn get_cursor(&self, info: &Value) -> Result<Value, errors::DbError> {
+        validation::check_string_fields_exist(info, &["cursor_type"])?;
+        let guard = self.log.read().await;
+        let sequence_number = match info.get("cursor_type").unwrap().as_str().unwrap() {
+            "TRIM_HORIZON" => {guard.first_sequence()}
+            "LATEST" => {guard.next_sequence}
+            "AT_SEQUENCE" => {
+                let Some(sequence_number) = info.get("sequence_number").and_then(|s| s.as_u64()) else {
+                    return Err(errors::DbError::MissingFields(String::from("sequence_number is required for AT_SEQUENCE")));
+                };
+                if sequence_number < guard.first_sequence() || sequence_number >= guard.next_sequence {
+                    return Err(errors::DbError::BadInput(format!("Sequence number {} is not in the stream", sequence_number)));
+                }
+                sequence_number
+            }
+            _ => {return Err(errors::DbError::BadInput(String::from("cursor_type must be TRIM_HORIZON, LATEST or AT_SEQUENCE")));}
+        };
+        Ok(serde_json::json!({
+            "cursor": self.encode_cursor(sequence_number),
+        }))
+    }
+
+    // next_cursor is always returned, so consumers can keep polling it for records written later
+    pub async fn read(&self, info: &Value) -> Result<Value, errors::DbError> {
+        let sequence_number = self.decode_cursor(info)?;
+        let limit = pagination::limit(info)?.unwrap_or(STREAM_READ_LIMIT).min(STREAM_READ_LIMIT);
+        let guard = self.log.read().await;
+        if sequence_number < guard.first_sequence() {
+            return Err(errors::DbError::BadInput(String::from("Cursor points at records that have been trimmed from the stream")));
+        }
+        if sequence_number > guard.next_sequence {
+            return Err(errors::DbError::BadInput(String::from("Invalid cursor")));
+        }
+        let skip = (sequence_number - guard.first_sequence()) as usize;
+        let records: Vec<Value> = guard.records.iter().skip(skip).take(limit).cloned().collect();
+        Ok(serde_json::json!({
+            "records": records,
+            "next_cursor": self.encode_cursor(sequence_number + records.len() as u64),
+        }))
+    }
+}
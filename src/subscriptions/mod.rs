@@ -0,0 +1,90 @@
+use tokio::sync::broadcast;
+use crate::*;
+
+// subscribers that fall this many events behind skip ahead and are told how many they missed
+const SUBSCRIPTION_BUFFER: usize = 1024;
+
+// every table can be subscribed to; nothing is built or sent while no one is listening
+pub struct Subscriptions {
+    sender: broadcast::Sender<(db_keys::KeyDatatype, Arc<Value>)>,
+}
+
+pub struct Subscription {
+    receiver: broadcast::Receiver<(db_keys::KeyDatatype, Arc<Value>)>,
+    partition_key: Option<db_keys::KeyDatatype>,
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(SUBSCRIPTION_BUFFER).0,
+        }
+    }
+}
+
+impl Subscriptions {
+    // must run while the partition holding the document is write locked, so events for a key keep write order
+    pub fn publish(&self, table_name: &str, pk: &db_keys::KeyDatatype, keys: impl FnOnce() -> Value, old: Option<&Value>, new: Option<&Value>) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let event_name = match (old, new) {
+            (None, None) => {return;}
+            (None, Some(_)) => {"INSERT"}
+            (Some(_), Some(_)) => {"MODIFY"}
+            (Some(_), None) => {"REMOVE"}
+        };
+        let event = serde_json::json!({
+            "table_name": table_name,
+            "event_name": event_name,
+            "keys": keys(),
+            "old_image": old,
+            "new_image": new,
+        });
+        let _ = self.sender.send((pk.clone(), Arc::new(event)));
+    }
+
+    // the partition key arrives as text, so it is read according to the key's datatype
+    pub fn subscribe(&self, primary_key: &db_keys::Key, partition_key: Option<&str>) -> Result<Subscription, errors::DbError> {
+        let partition_key = match partition_key {
+            None => {None}
+            Some(text) => {
+                let value = match primary_key.datatype {
+                    db_keys::KeyDatatype::String(_) => {Value::from(text)}
+                    _ => {
+                        serde_json::from_str(text).map_err(|_| {
+                            errors::DbError::BadInput(format!("partition_key must be a valid {}", primary_key.datatype.to_str()))
+                        })?
+                    }
+                };
+                Some(db_keys::parse_value(primary_key, &value)?)
+            }
+        };
+        Ok(Subscription {
+            receiver: self.sender.subscribe(),
+            partition_key,
+        })
+    }
+}
+
+impl Subscription {
+    // None once the table is gone
+    pub async fn next(&mut self) -> Option<Value> {
+        loop {
+            match self.receiver.recv().await {
+                Ok((pk, event)) => {
+                    if self.partition_key.as_ref().is_none_or(|k| *k == pk) {
+                        return Some(event.deref().clone());
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    return Some(serde_json::json!({
+                        "event_name": "LAGGED",
+                        "skipped": skipped,
+                    }));
+                }
+                Err(broadcast::error::RecvError::Closed) => {return None;}
+            }
+        }
+    }
+}
@@ -1,10 +1,197 @@
+use std::ops::Bound;
 use crate::*;
 
+fn string_list(info: &Value, field: &str) -> Result<Vec<String>, errors::DbError> {
+    match info.get(field) {
+        None | Some(Value::Null) => {Ok(Vec::new())}
+        Some(Value::Array(values)) if values.iter().all(|v| v.is_string()) => {
+            Ok(values.iter().map(|v| v.as_str().unwrap().to_string()).collect())
+        }
+        Some(_) => {Err(errors::DbError::BadInput(format!("{} must be an array of strings", field)))}
+    }
+}
+
+pub struct Partition {
+    pub documents: BTreeMap<db_keys::KeyDatatype, Value>,
+    // one sibling ordering of the same documents per local index, keyed by (index sort key, table sort key)
+    pub local_indexes: HashMap<String, BTreeSet<(db_keys::KeyDatatype, db_keys::KeyDatatype)>>,
+}
+
+impl Partition {
+    fn new() -> Self {
+        Self {
+            documents: BTreeMap::new(),
+            local_indexes: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, local_indexes: &[index::LocalIndex], sk: db_keys::KeyDatatype, document: Value) -> Option<Value> {
+        let old = self.remove(local_indexes, &sk);
+        for index in local_indexes {
+            if let Some(value) = index.sort_value(&document) {
+                self.local_indexes.entry(index.index_name.clone()).or_default().insert((value, sk.clone()));
+            }
+        }
+        self.documents.insert(sk, document);
+        old
+    }
+
+    fn remove(&mut self, local_indexes: &[index::LocalIndex], sk: &db_keys::KeyDatatype) -> Option<Value> {
+        let old = self.documents.remove(sk)?;
+        for index in local_indexes {
+            if let Some(value) = index.sort_value(&old)
+                && let Some(ordering) = self.local_indexes.get_mut(&index.index_name) {
+                ordering.remove(&(value, sk.clone()));
+            }
+        }
+        Some(old)
+    }
+}
+
+pub enum WriteKind {
+    Put,
+    Update,
+    Delete,
+    ConditionCheck,
+}
+
+#[derive(Clone, Copy)]
+pub enum ReturnValues {
+    None,
+    AllOld,
+    UpdatedOld,
+    AllNew,
+    UpdatedNew,
+}
+
+impl ReturnValues {
+    fn from_json(info: Option<&Value>) -> Result<Self, errors::DbError> {
+        match info {
+            None | Some(Value::Null) => {Ok(Self::None)}
+            Some(Value::String(s)) if s.eq("NONE") => {Ok(Self::None)}
+            Some(Value::String(s)) if s.eq("ALL_OLD") => {Ok(Self::AllOld)}
+            Some(Value::String(s)) if s.eq("UPDATED_OLD") => {Ok(Self::UpdatedOld)}
+            Some(Value::String(s)) if s.eq("ALL_NEW") => {Ok(Self::AllNew)}
+            Some(Value::String(s)) if s.eq("UPDATED_NEW") => {Ok(Self::UpdatedNew)}
+            Some(_) => {Err(errors::DbError::BadInput(String::from("return_values must be NONE, ALL_OLD, UPDATED_OLD, ALL_NEW or UPDATED_NEW")))}
+        }
+    }
+
+    // UPDATED_* keep only the top level attributes the write added, changed or removed
+    fn select(&self, old: Option<Value>, new: Option<Value>) -> Value {
+        let updated = |from: Option<&Value>, other: Option<&Value>| -> Value {
+            let Some(Value::Object(from)) = from else {return NULL_VAL;};
+            let output: serde_json::Map<String, Value> = from.iter()
+                .filter(|(name, value)| other.and_then(|o| o.get(name.as_str())) != Some(*value))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+            Value::Object(output)
+        };
+        let attributes = match self {
+            ReturnValues::None => {return NULL_VAL;}
+            ReturnValues::AllOld => {old.unwrap_or(NULL_VAL)}
+            ReturnValues::UpdatedOld => {updated(old.as_ref(), new.as_ref())}
+            ReturnValues::AllNew => {new.unwrap_or(NULL_VAL)}
+            ReturnValues::UpdatedNew => {updated(new.as_ref(), old.as_ref())}
+        };
+        serde_json::json!({
+            "attributes": attributes,
+        })
+    }
+}
+
+enum Change {
+    Put(Value),
+    Merge(Value),
+    Update(updates::UpdateExpression, Value),
+    Delete,
+    Check,
+}
+
+// how a write reaches the write-ahead log
+#[derive(Clone, Copy, PartialEq)]
+enum Logging {
+    // the write logs itself
+    Write,
+    // already logged, as part of a transaction
+    Logged,
+    // replayed from the log on startup, which also skips the stream and subscribers
+    Replay,
+}
+
+pub struct WriteRequest {
+    pk: db_keys::KeyDatatype,
+    sk: db_keys::KeyDatatype,
+    condition: Option<expressions::Condition>,
+    change: Change,
+    return_values: ReturnValues,
+}
+
+impl WriteRequest {
+    pub fn key(&self) -> (&db_keys::KeyDatatype, &db_keys::KeyDatatype) {
+        (&self.pk, &self.sk)
+    }
+
+    pub fn is_check(&self) -> bool {
+        matches!(self.change, Change::Check)
+    }
+
+    // what the document at this key becomes, given what is stored there now (None means absent)
+    fn apply(&self, current: Option<&Value>) -> Result<Option<Value>, errors::DbError> {
+        match &self.change {
+            Change::Put(document) => {Ok(Some(document.clone()))}
+            Change::Merge(document) => {
+                // merges only apply to documents that already exist
+                let Some(current) = current else {return Ok(None);};
+                let mut new = current.clone();
+                let output = new.as_object_mut().unwrap();
+                for (key, val) in document.as_object().unwrap() {
+                    output.insert(key.clone(), val.clone());
+                }
+                Ok(Some(new))
+            }
+            Change::Update(update, key) => {
+                // a missing document is created from its key, like an upsert
+                let mut new = current.cloned().unwrap_or_else(|| key.clone());
+                update.apply(&mut new)?;
+                Ok(Some(new))
+            }
+            Change::Delete => {Ok(None)}
+            Change::Check => {Ok(current.cloned())}
+        }
+    }
+}
+
+// an index is kept up to date by writes from the moment it is registered, while its backfill is still running
+struct IndexSlot {
+    index: std::sync::Mutex<index::GlobalIndex>,
+    ready: std::sync::atomic::AtomicBool,
+}
+
+impl IndexSlot {
+    fn is_ready(&self) -> bool {
+        self.ready.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
 pub struct Table {
     pub table_name: String,
     primary_key: db_keys::Key,
     sort_key: db_keys::Key,
-    data: Arc<RwLock<HashMap<db_keys::KeyDatatype, Arc<RwLock<BTreeMap<db_keys::KeyDatatype, Value>>>>>>,
+    data: Arc<RwLock<HashMap<db_keys::KeyDatatype, Arc<RwLock<Partition>>>>>,
+    // writes only read the map and lock the indexes one at a time, so they don't serialize on it
+    indexes: Arc<RwLock<HashMap<String, Arc<IndexSlot>>>>,
+    local_indexes: Vec<index::LocalIndex>,
+    // changed in place by update_table, so it's read through ttl()
+    ttl: std::sync::RwLock<Option<Arc<ttl::Ttl>>>,
+    // shared with the table a rebuild replaces this one with, so readers and subscribers carry on
+    stream: Option<Arc<stream::Stream>>,
+    backups: Option<backups::Backups>,
+    subscriptions: Arc<subscriptions::Subscriptions>,
+    created_at: f64,
+    wal: Option<Arc<wal::Wal>>,
+    // lsn of the last logged change, which tells a save whether the table's file is out of date
+    changed_lsn: std::sync::atomic::AtomicU64,
 }
 
 impl Table {
@@ -14,23 +201,66 @@ impl Table {
         validation::check_key_fields_exist(info, &["primary_key", "sort_key"])?;
         let mut output = Self {
             table_name: info.get("table_name").unwrap().as_str().unwrap().to_string(),
-            primary_key: db_keys::Key {
-                name: info.get("primary_key").unwrap().get("name").unwrap().as_str().unwrap().to_string(),
-                datatype: db_keys::KeyDatatype::from_str(info.get("primary_key").unwrap().get("datatype").unwrap().as_str().unwrap()),
-            },
-            sort_key: db_keys::Key {
-                name: info.get("sort_key").unwrap().get("name").unwrap().as_str().unwrap().to_string(),
-                datatype: db_keys::KeyDatatype::from_str(info.get("sort_key").unwrap().get("datatype").unwrap().as_str().unwrap()),
-            },
+            primary_key: db_keys::Key::from_json(info.get("primary_key").unwrap())?,
+            sort_key: db_keys::Key::from_json(info.get("sort_key").unwrap())?,
             data: Arc::new(RwLock::new(HashMap::new())),
+            indexes: Arc::new(RwLock::new(HashMap::new())),
+            local_indexes: Vec::new(),
+            ttl: std::sync::RwLock::new(ttl::Ttl::from_json(info.get("ttl"))?.map(Arc::new)),
+            stream: stream::Stream::from_json(info.get("stream"))?.map(Arc::new),
+            backups: backups::Backups::from_json(info.get("point_in_time_recovery"))?,
+            subscriptions: Arc::new(subscriptions::Subscriptions::default()),
+            // kept through saves, so only a brand new table takes the current time
+            created_at: info.get("created_at").and_then(|t| t.as_f64()).unwrap_or_else(ttl::now),
+            wal: None,
+            changed_lsn: std::sync::atomic::AtomicU64::new(0),
         };
-        if let Ok(data) = output.load(info).await {
-            output.data = Arc::new(RwLock::new(data));
+        match info.get("local_indexes") {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(definitions)) => {
+                for definition in definitions {
+                    let index = index::LocalIndex::new(definition)?;
+                    if output.local_indexes.iter().any(|i| i.index_name == index.index_name) {
+                        return Err(errors::DbError::BadInput(format!("Index {} already exists", index.index_name)));
+                    }
+                    output.local_indexes.push(index);
+                }
+            }
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("local_indexes must be a valid JSON array")));}
+        }
+        let mut indexes = Vec::new();
+        match info.get("global_indexes") {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(definitions)) => {
+                for definition in definitions {
+                    indexes.push(index::GlobalIndex::new(definition)?);
+                }
+            }
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("global_indexes must be a valid JSON array")));}
+        }
+        // a document that can't be loaded fails the whole table, rather than leaving it to be saved without it
+        match info.get("data") {
+            None | Some(Value::Null) => {}
+            Some(_) => {output.data = Arc::new(RwLock::new(output.load(info).await?));}
+        }
+        // a logged table brings its backups along, otherwise they start from the documents it was created with,
+        // until a saved table's own backups file is loaded over them
+        if let Some(backups) = &output.backups {
+            match info.get("backups") {
+                None | Some(Value::Null) => {
+                    let documents = info.get("data").and_then(|d| d.as_array()).map_or(&[][..], |d| d.as_slice());
+                    backups.set_base([&output.primary_key, &output.sort_key], documents)?;
+                }
+                Some(state) => {backups.load_state([&output.primary_key, &output.sort_key], state)?;}
+            }
+        }
+        for index in indexes {
+            output.add_index(index).await?;
         }
         Ok(output)
     }
 
-    pub async fn load(&self, info: &Value) -> Result<HashMap<db_keys::KeyDatatype, Arc<RwLock<BTreeMap<db_keys::KeyDatatype, Value>>>>, errors::DbError> {
+    pub async fn load(&self, info: &Value) -> Result<HashMap<db_keys::KeyDatatype, Arc<RwLock<Partition>>>, errors::DbError> {
         if info.get("data").is_none() {
             return Err(errors::DbError::MissingFields(String::from("Must contain field data")));
         }
@@ -39,111 +269,777 @@ impl Table {
             return Err(errors::DbError::BadInput(String::from("data must be valid JSON array")));
         }
         let data = data.as_array().unwrap();
-        let mut output: HashMap<db_keys::KeyDatatype, Arc<RwLock<BTreeMap<db_keys::KeyDatatype, Value>>>> = HashMap::new();
+        let mut output: HashMap<db_keys::KeyDatatype, Arc<RwLock<Partition>>> = HashMap::new();
         for document in data {
             let pk = db_keys::extract_key(&self.primary_key, document)?;
             let sk = db_keys::extract_key(&self.sort_key, document)?;
-            let mut inner_guard = output.entry(pk).or_insert(Arc::new(RwLock::new(BTreeMap::new()))).write().await;
-            inner_guard.deref_mut().insert(sk, document.clone());
+            let mut inner_guard = output.entry(pk).or_insert(Arc::new(RwLock::new(Partition::new()))).write().await;
+            inner_guard.deref_mut().insert(&self.local_indexes, sk, document.clone());
         }
         Ok(output)
     }
 
+    // partitions are read one at a time without holding the partition map, so writers are only held up
+    // by the partition being read; writes that land meanwhile may or may not be included
     pub async fn read_table(&self) -> Result<Value, errors::DbError> {
         let mut output = Vec::new();
-        let outer_guard = self.data.read().await;
-        for (_, val) in outer_guard.deref() {
+        let partitions: Vec<Arc<RwLock<Partition>>> = self.data.read().await.deref().values().cloned().collect();
+        for val in partitions {
             let inner_guard = val.read().await;
-            for (_, val) in inner_guard.deref() {
+            for val in inner_guard.documents.values() {
                 output.push(val.clone());
             }
         }
         Ok(serde_json::json!({
             "table_name": self.table_name,
-            "primary_key": serde_json::json!({
-                "name": self.primary_key.name,
-                "datatype": self.primary_key.datatype.to_str(),
-            }),
-            "sort_key": serde_json::json!({
-                "name": self.sort_key.name,
-                "datatype": self.sort_key.datatype.to_str(),
-            }),
+            "primary_key": self.primary_key.to_json(),
+            "sort_key": self.sort_key.to_json(),
+            "global_indexes": self.indexes.read().await.values().map(|slot| slot.index.lock().unwrap().to_json()).collect::<Vec<Value>>(),
+            "local_indexes": self.local_indexes.iter().map(|index| index.to_json()).collect::<Vec<Value>>(),
+            "ttl": self.ttl().map(|ttl| ttl.to_json()),
+            "stream": self.stream.as_ref().map(|stream| stream.to_json()),
+            "point_in_time_recovery": self.backups.as_ref().map(|backups| backups.to_json()),
+            "created_at": self.created_at,
             "data": Value::Array(output),
         }))
     }
 
-    pub async fn create_document(&self, info: Value) -> Result<Value, errors::DbError> {
-        let pk; let sk;
-        match db_keys::extract_key(&self.primary_key, &info) {
-            Ok(x) => {pk = x;}
-            Err(e) => {return Err(e);}
+    // what gets logged for the table, which includes its backups; saves write those to a file of their own
+    pub async fn to_snapshot(&self) -> Result<Value, errors::DbError> {
+        let mut output = self.read_table().await?;
+        if let Some(backups) = &self.backups {
+            output["backups"] = backups.state_to_json();
         }
-        match db_keys::extract_key(&self.sort_key, &info) {
-            Ok(x) => {sk = x;}
-            Err(e) => {return Err(e);}
+        Ok(output)
+    }
+
+    pub fn backups(&self) -> Option<&backups::Backups> {
+        self.backups.as_ref()
+    }
+
+    pub fn load_backups(&self, lines: &[Value], torn: bool) -> Result<(), errors::DbError> {
+        match &self.backups {
+            None => {Ok(())}
+            Some(backups) => {backups.load_file([&self.primary_key, &self.sort_key], lines, torn)}
         }
-        let mut outer_guard = self.data.write().await;
-        let mut inner_guard = outer_guard.deref_mut().entry(pk).or_insert(Arc::new(RwLock::new(BTreeMap::new()))).write().await;
-        inner_guard.deref_mut().insert(sk, info);
+    }
+
+    // metadata only; the byte size is what the documents take up serialized as JSON
+    pub async fn describe_table(&self) -> Result<Value, errors::DbError> {
+        let mut item_count = 0;
+        let mut size_bytes = 0;
+        let outer_guard = self.data.read().await;
+        for partition in outer_guard.deref().values() {
+            let inner_guard = partition.read().await;
+            item_count += inner_guard.documents.len();
+            size_bytes += inner_guard.documents.values().map(|d| serde_json::to_vec(d).map_or(0, |v| v.len())).sum::<usize>();
+        }
+        let global_indexes: Vec<Value> = self.indexes.read().await.values().map(|slot| {
+            let index = slot.index.lock().unwrap();
+            let mut output = index.to_json();
+            output["status"] = Value::from(if slot.is_ready() {"ACTIVE"} else {"CREATING"});
+            output["item_count"] = Value::from(index.item_count());
+            output
+        }).collect();
+        Ok(serde_json::json!({
+            "table_name": self.table_name,
+            "status": "ACTIVE",
+            "primary_key": self.primary_key.to_json(),
+            "sort_key": self.sort_key.to_json(),
+            "created_at": self.created_at,
+            "item_count": item_count,
+            "size_bytes": size_bytes,
+            "partition_count": outer_guard.deref().len(),
+            "global_indexes": global_indexes,
+            "local_indexes": self.local_indexes.iter().map(|index| index.to_json()).collect::<Vec<Value>>(),
+            "ttl": self.ttl().map(|ttl| ttl.to_json()),
+            "stream": self.stream.as_ref().map(|stream| stream.to_json()),
+            "point_in_time_recovery": self.backups.as_ref().map(|backups| backups.describe()),
+        }))
+    }
+
+    // an update that keeps the table's name, keys, local indexes, stream and backups can change it in place,
+    // which leaves its stream readers and subscribers undisturbed
+    pub fn updates_in_place(&self, info: &Value) -> Result<bool, errors::DbError> {
+        if info.get("table_name").is_some_and(|name| name.ne(self.table_name.as_str())) {
+            return Ok(false);
+        }
+        for (field, key) in [("primary_key", &self.primary_key), ("sort_key", &self.sort_key)] {
+            if info.get(field).is_some() {
+                validation::check_key_fields_exist(info, &[field])?;
+                if db_keys::Key::from_json(&info[field])?.to_json() != key.to_json() {
+                    return Ok(false);
+                }
+            }
+        }
+        if info.get("stream").is_some()
+            && stream::Stream::from_json(info.get("stream"))?.map(|s| s.to_json()) != self.stream.as_ref().map(|s| s.to_json()) {
+            return Ok(false);
+        }
+        if info.get("point_in_time_recovery").is_some()
+            && backups::Backups::from_json(info.get("point_in_time_recovery"))?.map(|b| b.to_json()) != self.backups.as_ref().map(|b| b.to_json()) {
+            return Ok(false);
+        }
+        let local_changed = !matches!(info.get("create_local_indexes"), None | Some(Value::Null))
+            || string_list(info, "delete_indexes")?.iter().any(|name| self.local_indexes.iter().any(|i| &i.index_name == name));
+        Ok(!local_changed)
+    }
+
+    // applies an update updates_in_place allows; it is all checked before anything changes
+    pub async fn update(&self, info: &Value) -> Result<(), errors::DbError> {
+        let ttl = match info.get("ttl") {
+            None => {None}
+            Some(ttl) => {Some(ttl::Ttl::from_json(Some(ttl))?.map(Arc::new))}
+        };
+        let deleted = string_list(info, "delete_indexes")?;
+        let mut created = Vec::new();
+        match info.get("create_global_indexes") {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(definitions)) => {
+                for definition in definitions {
+                    created.push(index::GlobalIndex::new(definition)?);
+                }
+            }
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("create_global_indexes must be a valid JSON array")));}
+        }
+        {
+            let index_guard = self.indexes.read().await;
+            for index_name in &deleted {
+                if !index_guard.contains_key(index_name) {
+                    return Err(errors::DbError::IndexNotFound(format!("Index {} does not exist", index_name)));
+                }
+            }
+            let mut names = HashSet::new();
+            for index in &created {
+                let exists = (index_guard.contains_key(&index.index_name) && !deleted.contains(&index.index_name))
+                    || self.local_indexes.iter().any(|i| i.index_name == index.index_name);
+                if exists || !names.insert(index.index_name.clone()) {
+                    return Err(errors::DbError::BadInput(format!("Index {} already exists", index.index_name)));
+                }
+            }
+        }
+        for index_name in &deleted {
+            self.delete_index(index_name).await?;
+        }
+        // the ttl goes first, so the new indexes are filled in with the expiry it gives
+        if let Some(ttl) = ttl {
+            *self.ttl.write().unwrap() = ttl;
+            self.refresh_expiry().await;
+        }
+        for index in created {
+            self.add_index(index).await?;
+        }
+        Ok(())
+    }
+
+    // index entries keep the expiry they were written with, so a new ttl rewrites them; each partition is write
+    // locked meanwhile, so a write to it lands either before, and is rewritten here, or after, with the new ttl
+    async fn refresh_expiry(&self) {
+        let partitions: Vec<(db_keys::KeyDatatype, Arc<RwLock<Partition>>)> = self.data.read().await.deref().iter()
+            .map(|(pk, partition)| (pk.clone(), partition.clone()))
+            .collect();
+        for (pk, partition) in partitions {
+            let inner_guard = partition.write().await;
+            let index_guard = self.indexes.read().await;
+            for slot in index_guard.values() {
+                let mut index = slot.index.lock().unwrap();
+                for (sk, document) in &inner_guard.documents {
+                    index.insert([&self.primary_key, &self.sort_key], &pk, sk, document, self.expires_at(document));
+                }
+            }
+        }
+    }
+
+    // builds the table an update_table request describes from this one's definition and documents;
+    // documents that don't fit the new key schema, or collide with another under it, are left out and returned
+    pub async fn rebuild(&self, info: &Value) -> Result<(Table, Vec<Value>), errors::DbError> {
+        let mut definition = self.to_snapshot().await?;
+        let documents = definition["data"].take();
+        definition["data"] = Value::Array(Vec::new());
+        let backups = definition.as_object_mut().unwrap().remove("backups");
+        for field in ["table_name", "primary_key", "sort_key", "ttl", "stream", "point_in_time_recovery"] {
+            if let Some(value) = info.get(field) {
+                definition[field] = value.clone();
+            }
+        }
+
+        let deleted = string_list(info, "delete_indexes")?;
+        for index_name in &deleted {
+            let exists = ["global_indexes", "local_indexes"].iter()
+                .any(|kind| definition[kind].as_array().unwrap().iter().any(|index| index["index_name"].eq(index_name.as_str())));
+            if !exists {
+                return Err(errors::DbError::IndexNotFound(format!("Index {} does not exist", index_name)));
+            }
+        }
+        for (kind, created) in [("global_indexes", "create_global_indexes"), ("local_indexes", "create_local_indexes")] {
+            let indexes = definition[kind].as_array_mut().unwrap();
+            indexes.retain(|index| !deleted.iter().any(|name| index["index_name"].eq(name.as_str())));
+            match info.get(created) {
+                None | Some(Value::Null) => {}
+                Some(Value::Array(definitions)) => {indexes.extend(definitions.iter().cloned());}
+                Some(_) => {return Err(errors::DbError::BadInput(format!("{} must be a valid JSON array", created)));}
+            }
+        }
+
+        // an empty table with the new schema sorts the documents before the real one is loaded with those that fit
+        let schema = Table::new(&definition).await?;
+        // backups are kept unless the documents are keyed differently, which their log can't follow
+        if let Some(backups) = backups
+            && schema.primary_key.to_json() == self.primary_key.to_json()
+            && schema.sort_key.to_json() == self.sort_key.to_json() {
+            definition["backups"] = backups;
+        }
+        let mut keys = HashSet::new();
+        let mut fitting = Vec::new();
+        let mut unfitting = Vec::new();
+        for document in documents.as_array().unwrap() {
+            let key = db_keys::extract_key(&schema.primary_key, document)
+                .and_then(|pk| Ok((pk, db_keys::extract_key(&schema.sort_key, document)?)));
+            let reason = match key {
+                Err(e) => {e.to_string()}
+                Ok(key) => {
+                    if keys.insert(key) {
+                        fitting.push(document.clone());
+                        continue;
+                    }
+                    String::from("Another document already has the same key under the new key schema")
+                }
+            };
+            unfitting.push(serde_json::json!({
+                "document": document,
+                "reason": reason,
+            }));
+        }
+        definition["data"] = Value::Array(fitting);
+        let mut table = Table::new(&definition).await?;
+        // subscribers follow the table through the rebuild, as does the stream unless it was reconfigured
+        table.subscriptions = self.subscriptions.clone();
+        if table.stream.as_ref().map(|s| s.to_json()) == self.stream.as_ref().map(|s| s.to_json()) {
+            table.stream = self.stream.clone();
+        }
+        Ok((table, unfitting))
+    }
+
+    // the index is registered before the backfill, so writes to a partition land in it either before the
+    // backfill reads that partition, which then inserts the same entry again, or after, when it is already there
+    async fn add_index(&self, index: index::GlobalIndex) -> Result<(), errors::DbError> {
+        let slot = {
+            let mut index_guard = self.indexes.write().await;
+            if index_guard.contains_key(&index.index_name) || self.local_indexes.iter().any(|i| i.index_name == index.index_name) {
+                return Err(errors::DbError::BadInput(format!("Index {} already exists", index.index_name)));
+            }
+            let slot = Arc::new(IndexSlot {
+                index: std::sync::Mutex::new(index),
+                ready: std::sync::atomic::AtomicBool::new(false),
+            });
+            index_guard.insert(slot.index.lock().unwrap().index_name.clone(), slot.clone());
+            slot
+        };
+        let partitions: Vec<(db_keys::KeyDatatype, Arc<RwLock<Partition>>)> = self.data.read().await.deref().iter()
+            .map(|(pk, partition)| (pk.clone(), partition.clone()))
+            .collect();
+        for (pk, partition) in partitions {
+            let inner_guard = partition.read().await;
+            let mut index = slot.index.lock().unwrap();
+            for (sk, document) in &inner_guard.documents {
+                index.insert([&self.primary_key, &self.sort_key], &pk, sk, document, self.expires_at(document));
+            }
+        }
+        slot.ready.store(true, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub async fn create_index(&self, info: Value) -> Result<Value, errors::DbError> {
+        self.add_index(index::GlobalIndex::new(&info)?).await?;
         Ok(NULL_VAL)
     }
 
-    pub async fn read_document(&self, info: Value) -> Result<Value, errors::DbError> {
-        let pk; let sk;
-        match db_keys::extract_key(&self.primary_key, &info) {
-            Ok(x) => {pk = x;}
-            Err(e) => {return Err(e);}
+    pub async fn delete_index(&self, index_name: &String) -> Result<Value, errors::DbError> {
+        match self.indexes.write().await.remove(index_name) {
+            None => {Err(errors::DbError::IndexNotFound(String::from("Index does not exist")))}
+            Some(_) => {Ok(NULL_VAL)}
         }
-        match db_keys::extract_key(&self.sort_key, &info) {
-            Ok(x) => {sk = x;}
-            Err(e) => {return Err(e);}
+    }
+
+    fn ttl(&self) -> Option<Arc<ttl::Ttl>> {
+        self.ttl.read().unwrap().clone()
+    }
+
+    fn expires_at(&self, document: &Value) -> Option<f64> {
+        self.ttl.read().unwrap().as_ref().and_then(|ttl| ttl.expires_at(document))
+    }
+
+    // expired documents stay stored until the sweeper removes them, but are never returned
+    fn is_live(&self, document: &Value, now: f64) -> bool {
+        self.ttl.read().unwrap().as_ref().is_none_or(|ttl| !ttl.is_expired(document, now))
+    }
+
+    fn key_to_json(&self, pk: &db_keys::KeyDatatype, sk: &db_keys::KeyDatatype) -> Value {
+        let mut output = serde_json::Map::new();
+        self.primary_key.insert_attributes(pk, &mut output);
+        if !matches!(self.sort_key.datatype, db_keys::KeyDatatype::Null(_)) {
+            self.sort_key.insert_attributes(sk, &mut output);
+        }
+        Value::Object(output)
+    }
+
+    // deletes every document whose expiry has passed, returning the keys it removed
+    pub async fn sweep_expired(&self, now: f64) -> Vec<Value> {
+        let Some(ttl) = self.ttl() else {return Vec::new();};
+        let mut expired = Vec::new();
+        {
+            let outer_guard = self.data.read().await;
+            for (pk, partition) in outer_guard.deref() {
+                let inner_guard = partition.read().await;
+                for (sk, document) in &inner_guard.documents {
+                    if ttl.is_expired(document, now) {
+                        expired.push((pk.clone(), sk.clone()));
+                    }
+                }
+            }
+        }
+        let condition = ttl.expired_condition(now);
+        let mut output = Vec::new();
+        for (pk, sk) in expired {
+            if let Ok((Some(_), _)) = self.write(pk.clone(), sk.clone(), Some(&condition), |_| Ok(None), Logging::Write).await {
+                output.push(self.key_to_json(&pk, &sk));
+            }
+        }
+        output
+    }
+
+    // must run while the partition holding the document is write locked, so index order matches write order
+    async fn update_indexes(&self, pk: &db_keys::KeyDatatype, sk: &db_keys::KeyDatatype, old: Option<&Value>, new: Option<&Value>) {
+        let index_guard = self.indexes.read().await;
+        for slot in index_guard.values() {
+            let mut index = slot.index.lock().unwrap();
+            if let Some(old) = old {
+                index.remove(pk, sk, old);
+            }
+            if let Some(new) = new {
+                index.insert([&self.primary_key, &self.sort_key], pk, sk, new, self.expires_at(new));
+            }
+        }
+    }
+
+    // writes may wrap their payload as {field: payload, condition, expression_names, expression_values};
+    // a bare payload carrying the primary key is the plain form
+    fn unwrap_request<'a>(&self, info: &'a Value, field: &str) -> (&'a Value, &'a Value) {
+        match info.get(field) {
+            Some(payload) if payload.is_object() && self.primary_key.attributes()[0].lookup(info).is_none() => {(payload, info)}
+            _ => {(info, &NULL_VAL)}
         }
+    }
+
+    // a missing document is checked as an empty one, so attribute_not_exists holds and comparisons fail
+    fn check_condition(condition: Option<&expressions::Condition>, current: Option<&Value>) -> Result<(), errors::DbError> {
+        match condition {
+            Some(condition) if !condition.evaluate(current.unwrap_or(&NULL_VAL)) => {
+                Err(errors::DbError::ConditionFailed(String::from("The conditional request failed")))
+            }
+            _ => {Ok(())}
+        }
+    }
+
+    // replaces the document at pk/sk with whatever apply returns for the current one (None removes it),
+    // returning the document before and after the write
+    async fn write(
+        &self,
+        pk: db_keys::KeyDatatype,
+        sk: db_keys::KeyDatatype,
+        condition: Option<&expressions::Condition>,
+        apply: impl FnOnce(Option<&Value>) -> Result<Option<Value>, errors::DbError>,
+        logging: Logging,
+    ) -> Result<(Option<Value>, Option<Value>), errors::DbError> {
         let outer_guard = self.data.read().await;
-        let inner_guard = outer_guard.deref().get(&pk);
-        if inner_guard.is_none() {return Ok(NULL_VAL);}
-        let inner_guard = inner_guard.unwrap().read().await;
-        let output = inner_guard.get(&sk);
-        if output.is_none() {
-            Ok(NULL_VAL)
-        } else {
-            Ok(output.unwrap().clone())
+        if let Some(partition) = outer_guard.deref().get(&pk) {
+            let mut inner_guard = partition.write().await;
+            let output = self.write_partition(inner_guard.deref_mut(), &pk, sk, condition, apply, logging).await;
+            let emptied = inner_guard.documents.is_empty();
+            drop(inner_guard);
+            drop(outer_guard);
+            if emptied {
+                self.remove_if_empty(&pk).await;
+            }
+            return output;
+        }
+        drop(outer_guard);
+
+        let mut outer_guard = self.data.write().await;
+        let partition = outer_guard.deref_mut().entry(pk.clone()).or_insert(Arc::new(RwLock::new(Partition::new()))).clone();
+        let mut inner_guard = partition.write().await;
+        let output = self.write_partition(inner_guard.deref_mut(), &pk, sk, condition, apply, logging).await;
+        if inner_guard.documents.is_empty() {
+            outer_guard.deref_mut().remove(&pk);
         }
+        output
     }
-    pub async fn update_document(&self, info: Value) -> Result<Value, errors::DbError> {
+
+    // writers hold the read lock while they use a partition, so under the write lock an empty one is safe to drop
+    async fn remove_if_empty(&self, pk: &db_keys::KeyDatatype) {
+        let mut outer_guard = self.data.write().await;
+        let Some(partition) = outer_guard.deref().get(pk) else {
+            return;
+        };
+        if partition.read().await.documents.is_empty() {
+            outer_guard.deref_mut().remove(pk);
+        }
+    }
+
+    async fn write_partition(
+        &self,
+        partition: &mut Partition,
+        pk: &db_keys::KeyDatatype,
+        sk: db_keys::KeyDatatype,
+        condition: Option<&expressions::Condition>,
+        apply: impl FnOnce(Option<&Value>) -> Result<Option<Value>, errors::DbError>,
+        logging: Logging,
+    ) -> Result<(Option<Value>, Option<Value>), errors::DbError> {
+        let current = partition.documents.get(&sk);
+        Self::check_condition(condition, current)?;
+        let new = apply(current)?;
+        // logged before anything changes, so a write that can't be made durable isn't applied either
+        if logging == Logging::Write && let Some(wal) = &self.wal && (current.is_some() || new.is_some()) {
+            let timestamp = ttl::now();
+            let record = match &new {
+                Some(document) => {serde_json::json!({"op": "put", "table": self.table_name, "document": document, "timestamp": timestamp})}
+                None => {serde_json::json!({"op": "delete", "table": self.table_name, "key": self.key_to_json(pk, &sk), "timestamp": timestamp})}
+            };
+            // backups are recorded while the log is held, so they stay in log order
+            wal.append(record, |lsn| {
+                self.mark_changed(lsn);
+                if let Some(backups) = &self.backups {
+                    backups.record(lsn, timestamp, vec![((pk.clone(), sk.clone()), self.key_to_json(pk, &sk), new.clone())]);
+                }
+            }).await?;
+        }
+        let old = match &new {
+            Some(new) => {partition.insert(&self.local_indexes, sk.clone(), new.clone())}
+            None => {partition.remove(&self.local_indexes, &sk)}
+        };
+        self.update_indexes(pk, &sk, old.as_ref(), new.as_ref()).await;
+        if logging == Logging::Replay {
+            return Ok((old, new));
+        }
+        if let Some(stream) = &self.stream {
+            stream.record(self.key_to_json(pk, &sk), old.as_ref(), new.as_ref()).await;
+        }
+        self.subscriptions.publish(&self.table_name, pk, || self.key_to_json(pk, &sk), old.as_ref(), new.as_ref());
+        Ok((old, new))
+    }
+
+    pub fn parse_write(&self, kind: WriteKind, info: &Value) -> Result<WriteRequest, errors::DbError> {
+        let (key, options) = self.unwrap_request(info, "key");
+        let (document, options, change) = match kind {
+            WriteKind::Put => {
+                let (document, options) = self.unwrap_request(info, "document");
+                (document, options, Change::Put(document.clone()))
+            }
+            WriteKind::Update => {
+                match options.get("update") {
+                    None => {
+                        let (document, options) = self.unwrap_request(info, "document");
+                        if !document.is_object() {
+                            return Err(errors::DbError::BadInput(String::from("Payload must be a valid JSON object!")));
+                        }
+                        (document, options, Change::Merge(document.clone()))
+                    }
+                    Some(Value::String(update)) => {
+                        let update = updates::UpdateExpression::parse(update, options)?;
+                        update.check_keys(&[&self.primary_key, &self.sort_key])?;
+                        (key, options, Change::Update(update, key.clone()))
+                    }
+                    Some(_) => {return Err(errors::DbError::BadInput(String::from("update must be a string")));}
+                }
+            }
+            WriteKind::Delete => {(key, options, Change::Delete)}
+            WriteKind::ConditionCheck => {
+                if options.get("condition").is_none() {
+                    return Err(errors::DbError::MissingFields(String::from("condition field is required for a condition check")));
+                }
+                (key, options, Change::Check)
+            }
+        };
+        Ok(WriteRequest {
+            pk: db_keys::extract_key(&self.primary_key, document)?,
+            sk: db_keys::extract_key(&self.sort_key, document)?,
+            condition: expressions::condition_field(options, "condition")?,
+            change,
+            return_values: ReturnValues::from_json(options.get("return_values"))?,
+        })
+    }
+
+    pub async fn execute(&self, request: WriteRequest) -> Result<(Option<Value>, Option<Value>), errors::DbError> {
+        self.write(request.pk.clone(), request.sk.clone(), request.condition.as_ref(), |current| request.apply(current), Logging::Write).await
+    }
+
+    // checks the request against the stored document without writing, returning the document it would leave behind
+    pub async fn prepare(&self, request: &WriteRequest) -> Result<Option<Value>, errors::DbError> {
+        let outer_guard = self.data.read().await;
+        let inner_guard = match outer_guard.deref().get(&request.pk) {
+            None => {None}
+            Some(partition) => {Some(partition.read().await)}
+        };
+        let current = inner_guard.as_ref().and_then(|p| p.documents.get(&request.sk));
+        Self::check_condition(request.condition.as_ref(), current)?;
+        request.apply(current)
+    }
+
+    // how one write of a transaction appears in the transaction's log record
+    pub fn transaction_item(&self, request: &WriteRequest, new: &Option<Value>) -> Value {
+        match new {
+            Some(document) => {serde_json::json!({"op": "put", "table": self.table_name, "document": document})}
+            None => {serde_json::json!({"op": "delete", "table": self.table_name, "key": self.key_to_json(&request.pk, &request.sk)})}
+        }
+    }
+
+    // called while the transaction's record is appended to the log, with this table's writes in it
+    pub fn transaction_logged(&self, lsn: u64, timestamp: f64, writes: &[(&WriteRequest, &Option<Value>)]) {
+        self.mark_changed(lsn);
+        if let Some(backups) = &self.backups {
+            backups.record(lsn, timestamp, writes.iter().map(|(request, new)| {
+                ((request.pk.clone(), request.sk.clone()), self.key_to_json(&request.pk, &request.sk), (*new).clone())
+            }).collect());
+        }
+    }
+
+    // applies a write of a transaction that has already been logged as a whole
+    pub async fn commit(&self, request: WriteRequest, new: Option<Value>) -> Result<(Option<Value>, Option<Value>), errors::DbError> {
+        self.write(request.pk, request.sk, None, |_| Ok(new), Logging::Logged).await
+    }
+
+    fn get_stream(&self) -> Result<&stream::Stream, errors::DbError> {
+        self.stream.as_deref().ok_or_else(|| errors::DbError::BadInput(String::from("Table does not have a stream enabled")))
+    }
+
+    pub async fn get_stream_cursor(&self, info: Value) -> Result<Value, errors::DbError> {
+        self.get_stream()?.get_cursor(&info).await
+    }
+
+    pub async fn read_stream(&self, info: Value) -> Result<Value, errors::DbError> {
+        self.get_stream()?.read(&info).await
+    }
+
+    pub fn subscribe(&self, partition_key: Option<&str>) -> Result<subscriptions::Subscription, errors::DbError> {
+        self.subscriptions.subscribe(&self.primary_key, partition_key)
+    }
+
+    pub fn attach_wal(&mut self, wal: Arc<wal::Wal>) {
+        self.wal = Some(wal);
+    }
+
+    pub fn mark_changed(&self, lsn: u64) {
+        self.changed_lsn.fetch_max(lsn, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn changed_lsn(&self) -> u64 {
+        self.changed_lsn.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    // applies the puts and deletes of one log record on startup, a lone write or this table's part of a transaction;
+    // both just set the document's final state, so replaying one twice is harmless
+    pub async fn replay(&self, record: &Value, writes: &[&Value]) -> Result<(), errors::DbError> {
+        let mut parsed = Vec::with_capacity(writes.len());
+        for write in writes {
+            let (document, new) = match write.get("op").and_then(|o| o.as_str()) {
+                Some("put") => {(&write["document"], Some(write["document"].clone()))}
+                Some("delete") => {(&write["key"], None)}
+                _ => {return Err(errors::DbError::BadInput(String::from("Unknown write-ahead log record")));}
+            };
+            parsed.push((db_keys::extract_key(&self.primary_key, document)?, db_keys::extract_key(&self.sort_key, document)?, new));
+        }
+        let lsn = record["lsn"].as_u64().unwrap_or_default();
+        if let Some(backups) = &self.backups {
+            let timestamp = record.get("timestamp").and_then(|t| t.as_f64()).unwrap_or_else(ttl::now);
+            backups.record(lsn, timestamp, parsed.iter().map(|(pk, sk, new)| ((pk.clone(), sk.clone()), self.key_to_json(pk, sk), new.clone())).collect());
+        }
+        for (pk, sk, new) in parsed {
+            self.write(pk, sk, None, |_| Ok(new), Logging::Replay).await?;
+        }
+        self.mark_changed(lsn);
+        Ok(())
+    }
+
+    // the definition of a new table holding this one's documents as they were at the requested time;
+    // streams and backups aren't carried over
+    pub async fn restore_definition(&self, info: &Value) -> Result<Value, errors::DbError> {
+        let Some(backups) = &self.backups else {
+            return Err(errors::DbError::BadInput(String::from("Point in time recovery is not enabled for this table")));
+        };
+        let timestamp = match info.get("timestamp") {
+            None | Some(Value::Null) => {ttl::now()}
+            Some(t) => {t.as_f64().ok_or_else(|| errors::DbError::BadInput(String::from("timestamp must be a number")))?}
+        };
+        let mut definition = self.read_table().await?;
+        definition["table_name"] = info["table_name"].clone();
+        definition["data"] = Value::Array(backups.documents_at(timestamp)?);
+        let fields = definition.as_object_mut().unwrap();
+        for field in ["created_at", "stream", "point_in_time_recovery"] {
+            fields.remove(field);
+        }
+        Ok(definition)
+    }
+
+    async fn execute_returning(&self, request: WriteRequest) -> Result<Value, errors::DbError> {
+        let return_values = request.return_values;
+        let (old, new) = self.execute(request).await?;
+        Ok(return_values.select(old, new))
+    }
+
+    pub async fn create_document(&self, info: Value) -> Result<Value, errors::DbError> {
+        self.execute_returning(self.parse_write(WriteKind::Put, &info)?).await
+    }
+
+    pub async fn read_document(&self, info: Value) -> Result<Value, errors::DbError> {
+        // the projection sits beside the key in either request form
+        let (key, _) = self.unwrap_request(&info, "key");
+        let projection = expressions::ProjectionExpression::from_json(&info)?;
         let pk; let sk;
-        match db_keys::extract_key(&self.primary_key, &info) {
+        match db_keys::extract_key(&self.primary_key, key) {
             Ok(x) => {pk = x;}
             Err(e) => {return Err(e);}
         }
-        match db_keys::extract_key(&self.sort_key, &info) {
+        match db_keys::extract_key(&self.sort_key, key) {
             Ok(x) => {sk = x;}
             Err(e) => {return Err(e);}
         }
         let outer_guard = self.data.read().await;
         let inner_guard = outer_guard.deref().get(&pk);
         if inner_guard.is_none() {return Ok(NULL_VAL);}
-        let mut inner_guard = inner_guard.unwrap().write().await;
-        let output = inner_guard.get_mut(&sk).unwrap().as_object_mut().unwrap();
-        for (key, val) in info.as_object().unwrap() {
-            output.insert(key.clone(), val.clone());
+        let inner_guard = inner_guard.unwrap().read().await;
+        let output = inner_guard.documents.get(&sk).filter(|d| self.is_live(d, ttl::now()));
+        match (output, projection) {
+            (None, _) => {Ok(NULL_VAL)}
+            (Some(document), None) => {Ok(document.clone())}
+            (Some(document), Some(projection)) => {Ok(projection.apply(document))}
         }
-        Ok(NULL_VAL)
+    }
+    pub async fn update_document(&self, info: Value) -> Result<Value, errors::DbError> {
+        self.execute_returning(self.parse_write(WriteKind::Update, &info)?).await
     }
     pub async fn delete_document(&self, info: Value) -> Result<Value, errors::DbError> {
-        let pk; let sk;
-        match db_keys::extract_key(&self.primary_key, &info) {
-            Ok(x) => {pk = x;}
-            Err(e) => {return Err(e);}
+        self.execute_returning(self.parse_write(WriteKind::Delete, &info)?).await
+    }
+
+    fn encode_position(&self, pk: &db_keys::KeyDatatype, sk: &db_keys::KeyDatatype) -> String {
+        pagination::encode_token(&serde_json::json!({
+            "pk": pk.to_value(),
+            "sk": sk.to_value(),
+        }))
+    }
+
+    fn decode_position(&self, position: &Value) -> Result<(db_keys::KeyDatatype, db_keys::KeyDatatype), errors::DbError> {
+        let invalid = || errors::DbError::BadInput(String::from("Continuation token does not belong to this table"));
+        if !position.is_object() || position.get("pk").is_none() || position.get("sk").is_none() {
+            return Err(invalid());
         }
-        match db_keys::extract_key(&self.sort_key, &info) {
-            Ok(x) => {sk = x;}
-            Err(e) => {return Err(e);}
+        let pk = db_keys::parse_value(&self.primary_key, position.get("pk").unwrap()).map_err(|_| invalid())?;
+        let sk = db_keys::parse_value(&self.sort_key, position.get("sk").unwrap()).map_err(|_| invalid())?;
+        Ok((pk, sk))
+    }
+
+    pub async fn query(&self, info: Value) -> Result<Value, errors::DbError> {
+        let now = ttl::now();
+        match info.get("index_name") {
+            None | Some(Value::Null) => {}
+            Some(Value::String(index_name)) => {
+                if let Some(index) = self.local_indexes.iter().find(|i| i.index_name.eq(index_name)) {
+                    let pk = db_keys::extract_key(&self.primary_key, &info)?;
+                    let outer_guard = self.data.read().await;
+                    return match outer_guard.deref().get(&pk) {
+                        None => {index.query([&self.primary_key, &self.sort_key], &pk, None, &info, |_| true)}
+                        Some(partition) => {
+                            let inner_guard = partition.read().await;
+                            index.query([&self.primary_key, &self.sort_key], &pk, Some(inner_guard.deref()), &info, |d| self.is_live(d, now))
+                        }
+                    };
+                }
+                let slot = self.indexes.read().await.deref().get(index_name).cloned();
+                return match slot {
+                    None => {Err(errors::DbError::IndexNotFound(String::from("Index does not exist")))}
+                    Some(slot) if !slot.is_ready() => {Err(errors::DbError::BadInput(format!("Index {} is still being created", index_name)))}
+                    Some(slot) => {slot.index.lock().unwrap().query([&self.primary_key, &self.sort_key], &info, now)}
+                };
+            }
+            Some(_) => {return Err(errors::DbError::BadInput(String::from("index_name must be a string")));}
         }
+        let pk = db_keys::extract_key(&self.primary_key, &info)?;
+        let query = query::Query::from_json(&self.sort_key, &info)?;
+        let start = match pagination::start_key(&info)? {
+            None => {None}
+            Some(position) => {
+                let (start_pk, start_sk) = self.decode_position(&position)?;
+                if start_pk != pk {
+                    return Err(errors::DbError::BadInput(String::from("Continuation token belongs to a different partition")));
+                }
+                Some(start_sk)
+            }
+        };
         let outer_guard = self.data.read().await;
-        let inner_guard = outer_guard.deref().get(&pk);
-        if inner_guard.is_none() {return Ok(NULL_VAL);}
-        let mut inner_guard = inner_guard.unwrap().write().await;
-        inner_guard.deref_mut().remove(&sk);
-        Ok(NULL_VAL)
+        let (output, last) = match outer_guard.deref().get(&pk) {
+            None => {(Vec::new(), None)}
+            Some(partition) => {
+                let inner_guard = partition.read().await;
+                let entries = inner_guard.documents.range(query.bounds()).filter(|(_, d)| self.is_live(d, now));
+                query.run(entries, |sk| sk, start.as_ref())
+            }
+        };
+        Ok(serde_json::json!({
+            "count": output.len(),
+            "data": Value::Array(output),
+            "last_evaluated_key": last.map(|sk| self.encode_position(&pk, &sk)),
+        }))
+    }
+
+    pub async fn scan(&self, info: Value) -> Result<Value, errors::DbError> {
+        let filter = expressions::condition_field(&info, "filter")?;
+        let projection = expressions::ProjectionExpression::from_json(&info)?;
+        let now = ttl::now();
+        let limit = pagination::limit(&info)?.unwrap_or(usize::MAX);
+        let start = match pagination::start_key(&info)? {
+            None => {None}
+            Some(position) => {Some(self.decode_position(&position)?)}
+        };
+        let mut output = Vec::new();
+        let mut scanned_count = 0;
+        let mut last = None;
+        let mut more = false;
+
+        // partitions are visited in key order so a continuation token can resume the walk
+        let outer_guard = self.data.read().await;
+        let mut partition_keys: Vec<&db_keys::KeyDatatype> = outer_guard.deref().keys().collect();
+        partition_keys.sort();
+        'partitions: for pk in partition_keys {
+            let range = match &start {
+                Some((start_pk, _)) if pk < start_pk => {continue;}
+                Some((start_pk, start_sk)) if pk == start_pk => {(Bound::Excluded(start_sk.clone()), Bound::Unbounded)}
+                _ => {(Bound::Unbounded, Bound::Unbounded)}
+            };
+            let inner_guard = outer_guard.deref().get(pk).unwrap().read().await;
+            for (sk, document) in inner_guard.documents.range(range) {
+                if scanned_count == limit {
+                    more = true;
+                    break 'partitions;
+                }
+                scanned_count += 1;
+                last = Some((pk.clone(), sk.clone()));
+                if self.is_live(document, now) && filter.as_ref().is_none_or(|f| f.evaluate(document)) {
+                    output.push(match &projection {
+                        None => {document.clone()}
+                        Some(projection) => {projection.apply(document)}
+                    });
+                }
+            }
+        }
+        let last_evaluated_key = match (more, last) {
+            (true, Some((pk, sk))) => {Some(self.encode_position(&pk, &sk))}
+            _ => {None}
+        };
+        Ok(serde_json::json!({
+            "count": output.len(),
+            "scanned_count": scanned_count,
+            "data": Value::Array(output),
+            "last_evaluated_key": last_evaluated_key,
+        }))
     }
 }
@@ -0,0 +1,48 @@
+use crate::*;
+
+pub fn now() -> f64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64()
+}
+
+// documents carry their expiry as epoch seconds in a number attribute; anything else never expires
+pub struct Ttl {
+    pub attribute_name: String,
+}
+
+impl Ttl {
+    pub fn from_json(info: Option<&Value>) -> Result<Option<Self>, errors::DbError> {
+        match info {
+            None | Some(Value::Null) => {Ok(None)}
+            Some(info) if info.is_object() => {
+                validation::check_string_fields_exist(info, &["attribute_name"])?;
+                Ok(Some(Self {
+                    attribute_name: info.get("attribute_name").unwrap().as_str().unwrap().to_string(),
+                }))
+            }
+            Some(_) => {Err(errors::DbError::BadInput(String::from("ttl must be a valid JSON object")))}
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "attribute_name": self.attribute_name,
+        })
+    }
+
+    pub fn expires_at(&self, document: &Value) -> Option<f64> {
+        document.get(&self.attribute_name).and_then(|t| t.as_f64())
+    }
+
+    pub fn is_expired(&self, document: &Value, now: f64) -> bool {
+        self.expires_at(document).is_some_and(|t| t <= now)
+    }
+
+    // guards the sweeper's delete, in case the document was given a new expiry since it was found
+    pub fn expired_condition(&self, now: f64) -> expressions::Condition {
+        expressions::Condition::Compare(
+            expressions::Operand::Path(expressions::Path(vec![expressions::PathElement::Attribute(self.attribute_name.clone())])),
+            expressions::Comparator::LessEqual,
+            expressions::Operand::Value(Value::from(now)),
+        )
+    }
+}
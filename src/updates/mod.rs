@@ -0,0 +1,373 @@
+use std::cmp::Ordering;
+use crate::*;
+use crate::expressions::{Path, PathElement, Token};
+
+enum SetOperand {
+    Path(Path),
+    Value(Value),
+    ListAppend(Box<SetOperand>, Box<SetOperand>),
+    IfNotExists(Path, Box<SetOperand>),
+}
+
+enum SetValue {
+    Operand(SetOperand),
+    Plus(SetOperand, SetOperand),
+    Minus(SetOperand, SetOperand),
+}
+
+pub struct UpdateExpression {
+    set: Vec<(Path, SetValue)>,
+    remove: Vec<Path>,
+    add: Vec<(Path, Value)>,
+    delete: Vec<(Path, Value)>,
+}
+
+fn invalid_path() -> errors::DbError {
+    errors::DbError::BadInput(String::from("The document path provided in the update expression is invalid for update"))
+}
+
+impl SetOperand {
+    fn parse(parser: &mut expressions::Parser) -> Result<Self, errors::DbError> {
+        if parser.is_function_call("list_append") {
+            parser.next();
+            parser.next();
+            let first = Self::parse(parser)?;
+            parser.expect(Token::Comma, ",")?;
+            let second = Self::parse(parser)?;
+            parser.expect(Token::RightParen, ")")?;
+            return Ok(Self::ListAppend(Box::new(first), Box::new(second)));
+        }
+        if parser.is_function_call("if_not_exists") {
+            parser.next();
+            parser.next();
+            let path = parser.parse_path()?;
+            parser.expect(Token::Comma, ",")?;
+            let fallback = Self::parse(parser)?;
+            parser.expect(Token::RightParen, ")")?;
+            return Ok(Self::IfNotExists(path, Box::new(fallback)));
+        }
+        match parser.peek() {
+            Some(Token::Value(name)) => {
+                let name = name.clone();
+                parser.next();
+                Ok(Self::Value(parser.resolve_value(&name)?))
+            }
+            _ => {Ok(Self::Path(parser.parse_path()?))}
+        }
+    }
+
+    // operands always read the document as it was before the update
+    fn evaluate(&self, document: &Value) -> Result<Value, errors::DbError> {
+        match self {
+            SetOperand::Path(path) => {
+                path.resolve(document).cloned().ok_or_else(|| {
+                    errors::DbError::BadInput(String::from("The provided expression refers to an attribute that does not exist in the document"))
+                })
+            }
+            SetOperand::Value(value) => {Ok(value.clone())}
+            SetOperand::ListAppend(first, second) => {
+                match (first.evaluate(document)?, second.evaluate(document)?) {
+                    (Value::Array(mut first), Value::Array(second)) => {
+                        first.extend(second);
+                        Ok(Value::Array(first))
+                    }
+                    _ => {Err(errors::DbError::BadInput(String::from("list_append requires two lists")))}
+                }
+            }
+            SetOperand::IfNotExists(path, fallback) => {
+                match path.resolve(document) {
+                    Some(value) => {Ok(value.clone())}
+                    None => {fallback.evaluate(document)}
+                }
+            }
+        }
+    }
+}
+
+fn arithmetic(a: &Value, b: &Value, subtract: bool) -> Result<Value, errors::DbError> {
+    let invalid = || errors::DbError::BadInput(String::from("Arithmetic in an update expression requires two numbers"));
+    let (Value::Number(a), Value::Number(b)) = (a, b) else {return Err(invalid());};
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        let output = if subtract {a.checked_sub(b)} else {a.checked_add(b)};
+        if let Some(output) = output {
+            return Ok(Value::from(output));
+        }
+    }
+    let (a, b) = (a.as_f64().ok_or_else(invalid)?, b.as_f64().ok_or_else(invalid)?);
+    let output = if subtract {a - b} else {a + b};
+    serde_json::Number::from_f64(output).map(Value::Number).ok_or_else(invalid)
+}
+
+impl SetValue {
+    fn parse(parser: &mut expressions::Parser) -> Result<Self, errors::DbError> {
+        let first = SetOperand::parse(parser)?;
+        match parser.peek() {
+            Some(Token::Plus) => {
+                parser.next();
+                Ok(Self::Plus(first, SetOperand::parse(parser)?))
+            }
+            Some(Token::Minus) => {
+                parser.next();
+                Ok(Self::Minus(first, SetOperand::parse(parser)?))
+            }
+            _ => {Ok(Self::Operand(first))}
+        }
+    }
+
+    fn evaluate(&self, document: &Value) -> Result<Value, errors::DbError> {
+        match self {
+            SetValue::Operand(operand) => {operand.evaluate(document)}
+            SetValue::Plus(a, b) => {arithmetic(&a.evaluate(document)?, &b.evaluate(document)?, false)}
+            SetValue::Minus(a, b) => {arithmetic(&a.evaluate(document)?, &b.evaluate(document)?, true)}
+        }
+    }
+}
+
+fn navigate<'a>(document: &'a mut Value, elements: &[PathElement]) -> Option<&'a mut Value> {
+    let mut current = document;
+    for element in elements {
+        current = match element {
+            PathElement::Attribute(name) => {current.as_object_mut()?.get_mut(name)?}
+            PathElement::Index(index) => {current.as_array_mut()?.get_mut(*index)?}
+        };
+    }
+    Some(current)
+}
+
+// the parent of the path must already exist; list indexes past the end append
+fn set_path(document: &mut Value, path: &Path, value: Value) -> Result<(), errors::DbError> {
+    let (last, parents) = path.0.split_last().unwrap();
+    let parent = navigate(document, parents).ok_or_else(invalid_path)?;
+    match (last, parent) {
+        (PathElement::Attribute(name), Value::Object(o)) => {o.insert(name.clone(), value);}
+        (PathElement::Index(index), Value::Array(a)) => {
+            if *index < a.len() {
+                a[*index] = value;
+            } else {
+                a.push(value);
+            }
+        }
+        _ => {return Err(invalid_path());}
+    }
+    Ok(())
+}
+
+fn remove_path(document: &mut Value, path: &Path) {
+    let (last, parents) = path.0.split_last().unwrap();
+    match (last, navigate(document, parents)) {
+        (PathElement::Attribute(name), Some(Value::Object(o))) => {o.remove(name);}
+        (PathElement::Index(index), Some(Value::Array(a))) if *index < a.len() => {a.remove(*index);}
+        _ => {}
+    }
+}
+
+fn same_element(a: &Value, b: &Value) -> bool {
+    expressions::compare_values(a, b) == Some(Ordering::Equal)
+}
+
+impl UpdateExpression {
+    pub fn parse(text: &str, request: &Value) -> Result<Self, errors::DbError> {
+        let mut parser = expressions::Parser::new(text, request)?;
+        let mut output = Self {
+            set: Vec::new(),
+            remove: Vec::new(),
+            add: Vec::new(),
+            delete: Vec::new(),
+        };
+        let mut seen = Vec::new();
+        while !parser.is_done() {
+            let clause = match parser.next() {
+                Some(Token::Identifier(s)) => {s.to_ascii_uppercase()}
+                _ => {return Err(errors::DbError::BadInput(String::from("Expected SET, REMOVE, ADD or DELETE in update expression")));}
+            };
+            if seen.contains(&clause) {
+                return Err(errors::DbError::BadInput(format!("The {} clause may only appear once in an update expression", clause)));
+            }
+            loop {
+                match clause.as_str() {
+                    "SET" => {
+                        let path = parser.parse_path()?;
+                        parser.expect(Token::Equal, "=")?;
+                        output.set.push((path, SetValue::parse(&mut parser)?));
+                    }
+                    "REMOVE" => {output.remove.push(parser.parse_path()?);}
+                    "ADD" | "DELETE" => {
+                        let path = parser.parse_path()?;
+                        let value = match parser.next() {
+                            Some(Token::Value(name)) => {parser.resolve_value(&name)?}
+                            _ => {return Err(errors::DbError::BadInput(format!("{} requires an expression value", clause)));}
+                        };
+                        if clause.eq("ADD") {output.add.push((path, value));} else {output.delete.push((path, value));}
+                    }
+                    _ => {return Err(errors::DbError::BadInput(format!("Unknown update clause {}", clause)));}
+                }
+                if parser.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                parser.next();
+            }
+            seen.push(clause);
+        }
+        if seen.is_empty() {
+            return Err(errors::DbError::BadInput(String::from("Update expression must not be empty")));
+        }
+        output.check_overlaps()?;
+        Ok(output)
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.set.iter().map(|(p, _)| p)
+            .chain(self.remove.iter())
+            .chain(self.add.iter().map(|(p, _)| p))
+            .chain(self.delete.iter().map(|(p, _)| p))
+    }
+
+    fn check_overlaps(&self) -> Result<(), errors::DbError> {
+        let paths: Vec<&Path> = self.paths().collect();
+        for (i, a) in paths.iter().enumerate() {
+            for b in &paths[i + 1..] {
+                let shared = a.0.len().min(b.0.len());
+                if a.0[..shared] == b.0[..shared] {
+                    return Err(errors::DbError::BadInput(String::from("Two document paths overlap in the update expression")));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_keys(&self, keys: &[&db_keys::Key]) -> Result<(), errors::DbError> {
+        // a path touches a key when one of the two leads into the other
+        for path in self.paths() {
+            for key in keys.iter().flat_map(|k| k.attributes()) {
+                let touches = path.0.iter().zip(&key.path).all(|(element, segment)| {
+                    matches!(element, PathElement::Attribute(name) if name == segment)
+                });
+                if touches {
+                    return Err(errors::DbError::BadInput(format!("Cannot update key attribute {}", key.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, document: &mut Value) -> Result<(), errors::DbError> {
+        let original = document.clone();
+        let mut values = Vec::with_capacity(self.set.len());
+        for (_, value) in &self.set {
+            values.push(value.evaluate(&original)?);
+        }
+        for ((path, _), value) in self.set.iter().zip(values) {
+            set_path(document, path, value)?;
+        }
+
+        // later list indexes go first so earlier removals don't shift them
+        let mut remove: Vec<&Path> = self.remove.iter().collect();
+        remove.sort_by_key(|path| {
+            let index = match path.0.last() {
+                Some(PathElement::Index(index)) => {*index}
+                _ => {0}
+            };
+            std::cmp::Reverse((path.0.len(), index))
+        });
+        for path in remove {
+            remove_path(document, path);
+        }
+
+        for (path, value) in &self.add {
+            let new = match (path.resolve(document), value) {
+                (None, Value::Number(_) | Value::Array(_)) => {value.clone()}
+                (Some(current @ Value::Number(_)), Value::Number(_)) => {arithmetic(current, value, false)?}
+                (Some(Value::Array(current)), Value::Array(added)) => {
+                    let mut union = current.clone();
+                    for element in added {
+                        if !union.iter().any(|x| same_element(x, element)) {
+                            union.push(element.clone());
+                        }
+                    }
+                    Value::Array(union)
+                }
+                _ => {return Err(errors::DbError::BadInput(String::from("ADD requires a number or a set of matching type")));}
+            };
+            set_path(document, path, new)?;
+        }
+
+        for (path, value) in &self.delete {
+            let Value::Array(removed) = value else {
+                return Err(errors::DbError::BadInput(String::from("DELETE requires a set value")));
+            };
+            match path.resolve(document) {
+                None => {}
+                Some(Value::Array(current)) => {
+                    let difference: Vec<Value> = current.iter().filter(|x| !removed.iter().any(|r| same_element(x, r))).cloned().collect();
+                    if difference.is_empty() {
+                        remove_path(document, path);
+                    } else {
+                        set_path(document, path, Value::Array(difference))?;
+                    }
+                }
+                Some(_) => {return Err(errors::DbError::BadInput(String::from("DELETE can only remove elements from a set")));}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn update(text: &str, values: Value, mut document: Value) -> Result<Value, errors::DbError> {
+        UpdateExpression::parse(text, &json!({"expression_values": values}))?.apply(&mut document)?;
+        Ok(document)
+    }
+
+    #[test]
+    fn rejects_overlapping_paths() {
+        let values = json!({":v": 1});
+        for text in ["SET a = :v, a = :v", "SET a.b = :v REMOVE a", "SET l[1] = :v REMOVE l[1].x", "ADD n :v SET n = :v"] {
+            assert!(matches!(update(text, values.clone(), json!({})), Err(errors::DbError::BadInput(_))), "{}", text);
+        }
+        assert!(update("SET a.b = :v, a.c = :v, l[0] = :v, l[1] = :v", values, json!({"a": {}, "l": []})).is_ok());
+    }
+
+    #[test]
+    fn removes_list_indexes_from_the_end() {
+        let document = json!({"l": [0, 1, 2, 3, 4], "m": {"l": [0, 1, 2]}});
+        assert_eq!(update("REMOVE l[0], l[3], l[1], m.l[0], m.l[2]", json!({}), document).unwrap(), json!({"l": [2, 4], "m": {"l": [1]}}));
+    }
+
+    #[test]
+    fn adds_and_deletes_set_elements() {
+        let values = json!({":add": [2, 3], ":delete": ["a", "c"], ":all": ["a", "b"]});
+        let document = json!({"n": [1, 2], "s": ["a", "b", "c"]});
+        assert_eq!(update("ADD n :add, m :add DELETE s :delete", values.clone(), document.clone()).unwrap(), json!({
+            "n": [1, 2, 3],
+            "m": [2, 3],
+            "s": ["b"],
+        }));
+        // deleting every element removes the set, and deleting from one that isn't there does nothing
+        assert_eq!(update("DELETE s :all, missing :all", values.clone(), json!({"s": ["b", "a"]})).unwrap(), json!({}));
+        assert!(update("DELETE n :all", values, json!({"n": 1})).is_err());
+    }
+
+    #[test]
+    fn sets_only_missing_attributes_with_if_not_exists() {
+        let values = json!({":zero": 0, ":one": 1});
+        let text = "SET c = if_not_exists(c, :zero) + :one, d = if_not_exists(d, c)";
+        assert_eq!(update(text, values.clone(), json!({"c": 5})).unwrap(), json!({"c": 6, "d": 5}));
+        assert_eq!(update(text, values.clone(), json!({"c": 5, "d": "kept"})).unwrap(), json!({"c": 6, "d": "kept"}));
+        // operands read the document as it was before the update, so d can't see the new c
+        assert!(update(text, values, json!({})).is_err());
+    }
+
+    #[test]
+    fn appends_lists_with_list_append() {
+        let values = json!({":more": [3, 4], ":one": 1});
+        let document = json!({"l": [1, 2]});
+        assert_eq!(update("SET l = list_append(l, :more)", values.clone(), document.clone()).unwrap(), json!({"l": [1, 2, 3, 4]}));
+        assert_eq!(update("SET l = list_append(:more, l)", values.clone(), document.clone()).unwrap(), json!({"l": [3, 4, 1, 2]}));
+        assert_eq!(update("SET l = list_append(if_not_exists(m, :more), l)", values.clone(), document.clone()).unwrap(), json!({"l": [3, 4, 1, 2]}));
+        assert!(update("SET l = list_append(l, :one)", values, document).is_err());
+    }
+}
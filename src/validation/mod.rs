@@ -16,11 +16,20 @@ pub fn check_key_fields_exist(value: &Value, fields: &[&str]) -> Result<(), erro
         if value.get(field).is_none() {
             return Err(errors::DbError::MissingFields(format!("{} field is missing or null", field)));
         }
+        // composite keys are an array of attribute definitions
         let key_obj = value.get(field).unwrap();
-        if !key_obj.is_object() {
-            return Err(errors::DbError::BadInput(format!("{} must be a valid JSON object", field)))
+        match key_obj {
+            Value::Object(_) => {check_string_fields_exist(key_obj, &["name", "datatype"])?}
+            Value::Array(attributes) if !attributes.is_empty() => {
+                for attribute in attributes {
+                    if !attribute.is_object() {
+                        return Err(errors::DbError::BadInput(format!("{} attributes must be valid JSON objects", field)))
+                    }
+                    check_string_fields_exist(attribute, &["name", "datatype"])?
+                }
+            }
+            _ => {return Err(errors::DbError::BadInput(format!("{} must be a valid JSON object or a non-empty array of them", field)))}
         }
-        check_string_fields_exist(key_obj, &["name", "datatype"])?
     }
     Ok(())
 }
\ No newline at end of file
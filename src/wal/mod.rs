@@ -0,0 +1,446 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use crate::*;
+
+pub enum SyncPolicy {
+    // fsync before every write is acknowledged
+    Always,
+    // fsync at most this long after a write; a crash of the machine can lose that much, a crash of the process nothing
+    Interval(Duration),
+    // leave flushing to the operating system
+    Never,
+}
+
+impl SyncPolicy {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "always" | "0" => {Ok(Self::Always)}
+            "never" => {Ok(Self::Never)}
+            _ => {
+                s.parse().map(|ms| Self::Interval(Duration::from_millis(ms)))
+                    .map_err(|_| String::from("LAWS_WAL_SYNC must be always, never or a number of milliseconds"))
+            }
+        }
+    }
+}
+
+struct WalState {
+    file: std::fs::File,
+    // bytes of whole records in the current file; a failed append is cut back to it
+    len: u64,
+    next_lsn: u64,
+    // records in the current file
+    records: usize,
+    // records written but not yet acknowledged, oldest first, with where each ends in the file
+    pending: VecDeque<Pending>,
+    // the last acknowledged record and where it ends; a failed fsync cuts the file back to there
+    acknowledged_lsn: u64,
+    acknowledged_len: u64,
+    synced_lsn: u64,
+    last_sync: Instant,
+    // appends wait while the current file is moved aside
+    rotating: bool,
+    // set once the log can't be trusted to hold exactly the acknowledged writes; every later append is refused
+    failed: Option<String>,
+}
+
+struct Pending {
+    lsn: u64,
+    end: u64,
+    // the append was dropped before acknowledging its record, which stays in the log like any write whose caller
+    // never heard back
+    abandoned: bool,
+}
+
+impl WalState {
+    // acknowledges abandoned records at the front, so the appends behind them aren't kept waiting
+    fn settle(&mut self) {
+        while let Some(pending) = self.pending.front() && pending.abandoned {
+            let pending = self.pending.pop_front().unwrap();
+            self.acknowledged_lsn = pending.lsn;
+            self.acknowledged_len = pending.end;
+        }
+    }
+
+    fn check(&self) -> Result<(), errors::DbError> {
+        match &self.failed {
+            None => {Ok(())}
+            Some(reason) => {Err(errors::DbError::StorageError(format!("Write-ahead log is refusing writes after an earlier failure: {}", reason)))}
+        }
+    }
+
+    // cuts a partly written record back out; if even that fails, nothing more is written to the file
+    fn roll_back(&mut self, e: std::io::Error) -> errors::DbError {
+        if let Err(truncate_error) = self.file.set_len(self.len) {
+            self.failed = Some(format!("{}, and could not remove the partial record: {}", e, truncate_error));
+        }
+        storage_error(e)
+    }
+
+    // after a failed fsync the file's pages can't be trusted to reach the disk anymore, so every record not yet
+    // acknowledged is cut back out, failing the writes waiting on them, and the log refuses writes from then on
+    fn fail(&mut self, e: std::io::Error) -> errors::DbError {
+        self.failed = Some(e.to_string());
+        if let Err(truncate_error) = self.file.set_len(self.acknowledged_len) {
+            self.failed = Some(format!("{}, and could not remove the unacknowledged records: {}", e, truncate_error));
+        }
+        self.len = self.acknowledged_len;
+        self.pending.clear();
+        storage_error(e)
+    }
+}
+
+// one JSON record per line, each stamped with an increasing log sequence number (lsn); records are appended to
+// wal.log, which a save moves aside as wal.<last lsn>.log so it can be deleted once nothing needs it
+pub struct Wal {
+    path: std::path::PathBuf,
+    policy: SyncPolicy,
+    // never held across file syncs, only while a record is written or acknowledged
+    state: std::sync::Mutex<WalState>,
+    // one fsync at a time, each covering every record written before it started
+    syncing: tokio::sync::Mutex<()>,
+    // sent whenever records are acknowledged or the log fails, for the appends waiting on either
+    progress: tokio::sync::watch::Sender<()>,
+    appended: tokio::sync::Notify,
+}
+
+fn storage_error(e: std::io::Error) -> errors::DbError {
+    errors::DbError::StorageError(format!("Write-ahead log: {}", e))
+}
+
+// a crash at any point leaves either the old file or the new one in place, never a partial one
+pub fn write_atomically(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    // the rename itself is only durable once the directory is synced
+    if let Some(dir) = path.parent() {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// replaces the log with the given records, returning it opened for appending
+fn write_records(path: &std::path::Path, records: &[Value]) -> Result<std::fs::File, errors::DbError> {
+    let mut buffer = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut buffer, record).unwrap();
+        buffer.push(b'\n');
+    }
+    write_atomically(path, &buffer).map_err(storage_error)?;
+    std::fs::OpenOptions::new().append(true).open(path).map_err(storage_error)
+}
+
+fn segment_path(path: &std::path::Path, last_lsn: u64) -> std::path::PathBuf {
+    path.with_extension(format!("{}.log", last_lsn))
+}
+
+// the log files moved aside by saves, oldest first, with the last lsn each holds
+fn segments(path: &std::path::Path) -> Result<Vec<(u64, std::path::PathBuf)>, errors::DbError> {
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem().and_then(|s| s.to_str())) else {
+        return Ok(Vec::new());
+    };
+    let mut output = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(storage_error)?.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let last_lsn = file_name.strip_prefix(stem)
+            .and_then(|n| n.strip_prefix('.'))
+            .and_then(|n| n.strip_suffix(".log"))
+            .and_then(|n| n.parse::<u64>().ok());
+        if let Some(last_lsn) = last_lsn {
+            output.push((last_lsn, entry.path()));
+        }
+    }
+    output.sort();
+    Ok(output)
+}
+
+fn sync_dir(path: &std::path::Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) => {std::fs::File::open(dir)?.sync_all()}
+        None => {Ok(())}
+    }
+}
+
+impl Wal {
+    // the records of every log file, oldest first, and the names of the files that are damaged
+    pub fn read(path: &std::path::Path) -> Result<(Vec<Value>, Vec<String>), errors::DbError> {
+        let mut files: Vec<std::path::PathBuf> = segments(path)?.into_iter().map(|(_, file_path)| file_path).collect();
+        files.push(path.to_path_buf());
+        let mut output = Vec::new();
+        let mut damaged = Vec::new();
+        for file_path in files {
+            if Self::read_file(&file_path, &mut output)? {
+                damaged.push(file_path.file_name().unwrap().to_string_lossy().to_string());
+            }
+        }
+        Ok((output, damaged))
+    }
+
+    // records are read up to the first line that doesn't parse; the file is only reported damaged when that
+    // isn't an unfinished last line, which is all a crash in the middle of an append can leave behind
+    fn read_file(path: &std::path::Path, output: &mut Vec<Value>) -> Result<bool, errors::DbError> {
+        let mut contents = Vec::new();
+        match std::fs::File::open(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {return Ok(false);}
+            Err(e) => {return Err(storage_error(e));}
+            Ok(mut file) => {file.read_to_end(&mut contents).map_err(storage_error)?;}
+        }
+        let mut lines: Vec<&[u8]> = contents.split(|b| *b == b'\n').collect();
+        // whatever follows the last newline is an append that never finished
+        lines.pop();
+        for line in lines {
+            match serde_json::from_slice::<Value>(line) {
+                Ok(record) if record.get("lsn").and_then(|l| l.as_u64()).is_some() => {output.push(record);}
+                _ => {return Ok(true);}
+            }
+        }
+        Ok(false)
+    }
+
+    // rewrites the log as a single file with just the given records, dropping anything a crash left half written
+    pub fn open(path: &std::path::Path, policy: SyncPolicy, records: &[Value], next_lsn: u64) -> Result<Self, errors::DbError> {
+        let file = write_records(path, records)?;
+        let len = file.metadata().map_err(storage_error)?.len();
+        for (_, file_path) in segments(path)? {
+            std::fs::remove_file(file_path).map_err(storage_error)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            policy,
+            state: std::sync::Mutex::new(WalState {
+                file,
+                len,
+                next_lsn,
+                records: records.len(),
+                pending: VecDeque::new(),
+                acknowledged_lsn: next_lsn - 1,
+                acknowledged_len: len,
+                synced_lsn: next_lsn - 1,
+                last_sync: Instant::now(),
+                rotating: false,
+                failed: None,
+            }),
+            syncing: tokio::sync::Mutex::new(()),
+            progress: tokio::sync::watch::Sender::new(()),
+            appended: tokio::sync::Notify::new(),
+        })
+    }
+
+    // the record is written under the log's lock, synced without it as the policy asks, and acknowledged in log
+    // order: written runs then, before the log is released, so anything it marks is in place by the time last_lsn
+    // covers the record. a record that fails is cut back out of the file, so a write the caller is told failed
+    // never comes back on startup
+    pub async fn append(self: &Arc<Self>, mut record: Value, written: impl FnOnce(u64)) -> Result<u64, errors::DbError> {
+        let mut progress = self.progress.subscribe();
+        let (lsn, due) = loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                state.check()?;
+                if !state.rotating {
+                    let lsn = state.next_lsn;
+                    record["lsn"] = Value::from(lsn);
+                    let mut line = serde_json::to_vec(&record).unwrap();
+                    line.push(b'\n');
+                    if let Err(e) = state.file.write_all(&line) {
+                        return Err(state.roll_back(e));
+                    }
+                    state.len += line.len() as u64;
+                    state.next_lsn += 1;
+                    state.records += 1;
+                    let end = state.len;
+                    state.pending.push_back(Pending {lsn, end, abandoned: false});
+                    let due = match self.policy {
+                        SyncPolicy::Always => {true}
+                        SyncPolicy::Interval(interval) => {state.last_sync.elapsed() >= interval}
+                        SyncPolicy::Never => {false}
+                    };
+                    if due {
+                        state.last_sync = Instant::now();
+                    }
+                    break (lsn, due);
+                }
+            }
+            let _ = progress.changed().await;
+        };
+        let mut guard = PendingGuard {wal: self, lsn, acknowledged: false};
+        if due {
+            self.sync_through(lsn).await?;
+        }
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                state.check()?;
+                if state.pending.front().is_some_and(|pending| pending.lsn == lsn) {
+                    let pending = state.pending.pop_front().unwrap();
+                    state.acknowledged_lsn = pending.lsn;
+                    state.acknowledged_len = pending.end;
+                    written(lsn);
+                    state.settle();
+                    guard.acknowledged = true;
+                    break;
+                }
+            }
+            let _ = progress.changed().await;
+        }
+        self.progress.send_replace(());
+        self.appended.notify_one();
+        Ok(lsn)
+    }
+
+    // resolves after the next append, or straight away if there was one since the last call
+    pub async fn appended(&self) {
+        self.appended.notified().await
+    }
+
+    // makes every record up to lsn durable; the fsync runs on the blocking pool with the log unlocked,
+    // so writers keep appending meanwhile and the next fsync covers them all
+    async fn sync_through(self: &Arc<Self>, lsn: u64) -> Result<(), errors::DbError> {
+        // run as its own task, so an fsync's outcome is recorded even if the writer waiting on it goes away
+        let wal = self.clone();
+        tokio::spawn(async move {wal.sync_now(lsn).await}).await.unwrap()
+    }
+
+    async fn sync_now(&self, lsn: u64) -> Result<(), errors::DbError> {
+        let _syncing = self.syncing.lock().await;
+        let (file, target) = {
+            let state = self.state.lock().unwrap();
+            state.check()?;
+            if state.synced_lsn >= lsn {
+                return Ok(());
+            }
+            (state.file.try_clone(), state.next_lsn - 1)
+        };
+        let synced = match file {
+            Ok(file) => {tokio::task::spawn_blocking(move || file.sync_data()).await.unwrap()}
+            Err(e) => {Err(e)}
+        };
+        let mut state = self.state.lock().unwrap();
+        match synced {
+            Ok(_) => {
+                state.synced_lsn = state.synced_lsn.max(target);
+                Ok(())
+            }
+            Err(e) => {
+                let e = state.fail(e);
+                drop(state);
+                self.progress.send_replace(());
+                Err(e)
+            }
+        }
+    }
+
+    // lets the interval policy catch up on writes that came in too close together to sync at the time
+    pub async fn sync(self: &Arc<Self>) -> Result<(), errors::DbError> {
+        if matches!(self.policy, SyncPolicy::Never) {
+            return Ok(());
+        }
+        let lsn = {
+            let mut state = self.state.lock().unwrap();
+            state.last_sync = Instant::now();
+            state.next_lsn - 1
+        };
+        self.sync_through(lsn).await
+    }
+
+    // the last acknowledged record
+    pub fn last_lsn(&self) -> u64 {
+        self.state.lock().unwrap().acknowledged_lsn
+    }
+
+    // drops the records a snapshot now covers, keeping the ones written while it was taken; appends only wait while
+    // the ones in flight are acknowledged and the current file is moved aside, the old files are synced and deleted after
+    pub async fn truncate(self: &Arc<Self>, through: u64) -> Result<(), errors::DbError> {
+        // run as its own task, so appends can't be left waiting on a rotation that was dropped halfway
+        let wal = self.clone();
+        tokio::spawn(async move {wal.rotate(through).await}).await.unwrap()
+    }
+
+    async fn rotate(&self, through: u64) -> Result<(), errors::DbError> {
+        let mut progress = self.progress.subscribe();
+        let last_lsn = loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                state.check()?;
+                // an empty file is left alone, moving it aside could replace the last file moved aside
+                if state.records == 0 {
+                    break None;
+                }
+                state.rotating = true;
+                if state.pending.is_empty() {
+                    break Some(state.next_lsn - 1);
+                }
+            }
+            let _ = progress.changed().await;
+        };
+
+        if let Some(last_lsn) = last_lsn {
+            let (path, segment) = (self.path.clone(), segment_path(&self.path, last_lsn));
+            let rotated = tokio::task::spawn_blocking(move || {
+                std::fs::rename(&path, &segment)?;
+                // appends acknowledged from here on have to find the new file after a crash
+                let opened = std::fs::OpenOptions::new().create(true).append(true).open(&path)
+                    .and_then(|file| sync_dir(&path).map(|_| file));
+                if opened.is_err() {
+                    // appends keep going to the old file, which has to be found under its own name again
+                    std::fs::rename(&segment, &path)?;
+                }
+                opened
+            }).await.unwrap();
+            let old = {
+                let mut state = self.state.lock().unwrap();
+                state.rotating = false;
+                match rotated {
+                    Ok(file) => {
+                        state.len = 0;
+                        state.acknowledged_len = 0;
+                        state.records = 0;
+                        Ok(std::mem::replace(&mut state.file, file))
+                    }
+                    Err(e) => {Err(storage_error(e))}
+                }
+            };
+            self.progress.send_replace(());
+            let old = old?;
+            // the records moved aside are caught up with the policy, the interval syncs only reach the new file
+            if !matches!(self.policy, SyncPolicy::Never) {
+                tokio::task::spawn_blocking(move || old.sync_data()).await.unwrap().map_err(storage_error)?;
+            }
+        }
+
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            for (last_lsn, file_path) in segments(&path)? {
+                if last_lsn <= through {
+                    std::fs::remove_file(file_path).map_err(storage_error)?;
+                }
+            }
+            sync_dir(&path).map_err(storage_error)
+        }).await.unwrap()
+    }
+}
+
+struct PendingGuard<'a> {
+    wal: &'a Wal,
+    lsn: u64,
+    acknowledged: bool,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if self.acknowledged {
+            return;
+        }
+        let mut state = self.wal.state.lock().unwrap();
+        if let Some(pending) = state.pending.iter_mut().find(|pending| pending.lsn == self.lsn) {
+            pending.abandoned = true;
+        }
+        state.settle();
+        drop(state);
+        self.wal.progress.send_replace(());
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use laws::config::{Config, CorruptionPolicy};
+use laws::database::Database;
+use laws::wal::SyncPolicy;
+use serde_json::{json, Value};
+
+const DOCUMENTS: usize = 5000;
+const GROUPS: usize = 4;
+
+async fn open(name: &str) -> (std::path::PathBuf, Database) {
+    let dir = std::env::temp_dir().join(format!("laws-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let db = Database::with_config(dir.to_str().unwrap(), Config {
+        wal_sync: SyncPolicy::Never,
+        autosave_seconds: 0,
+        autosave_mutations: 0,
+        on_corruption: CorruptionPolicy::Quarantine,
+    }).await.unwrap();
+    (dir, db)
+}
+
+// puts, moves between groups, strips of their group and deletes documents until told to stop
+async fn write(db: Database, table_name: String, seed: u64, stop: Arc<AtomicBool>, writes: Arc<AtomicUsize>) {
+    let mut state = seed;
+    while !stop.load(Ordering::SeqCst) {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let id = format!("d{}", (state >> 33) as usize % (DOCUMENTS + 1000));
+        let group = format!("g{}", (state >> 20) as usize % (GROUPS + 1));
+        match (state >> 60) % 4 {
+            0 => {db.create_document(&table_name, json!({"id": id, "g": group, "seed": seed})).await.unwrap();}
+            1 => {let _ = db.update_document(&table_name, json!({"id": id, "g": group, "updated": seed})).await;}
+            2 => {db.create_document(&table_name, json!({"id": id, "seed": seed})).await.unwrap();}
+            _ => {db.delete_document(&table_name, json!({"id": id})).await.unwrap();}
+        }
+        writes.fetch_add(1, Ordering::SeqCst);
+        tokio::task::yield_now().await;
+    }
+}
+
+fn by_id(documents: &Value) -> BTreeMap<String, Value> {
+    documents.as_array().unwrap().iter()
+        .map(|document| (document["id"].as_str().unwrap().to_string(), document.clone()))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn an_index_created_during_writes_matches_the_table() {
+    let (dir, db) = open("index-backfill").await;
+    let table_name = String::from("t");
+    db.create_table(json!({
+        "table_name": table_name,
+        "primary_key": {"name": "id", "datatype": "String"},
+        "sort_key": {"name": "Null", "datatype": "Null"},
+    })).await.unwrap();
+    for i in 0..DOCUMENTS {
+        db.create_document(&table_name, json!({"id": format!("d{}", i), "g": format!("g{}", i % GROUPS)})).await.unwrap();
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let writes = Arc::new(AtomicUsize::new(0));
+    let writers: Vec<_> = (0..4)
+        .map(|seed| tokio::spawn(write(db.clone(), table_name.clone(), seed, stop.clone(), writes.clone())))
+        .collect();
+    while writes.load(Ordering::SeqCst) < 100 {
+        tokio::task::yield_now().await;
+    }
+    let before = writes.load(Ordering::SeqCst);
+    db.create_index(&table_name, json!({
+        "index_name": "by_g",
+        "primary_key": {"name": "g", "datatype": "String"},
+    })).await.unwrap();
+    assert!(writes.load(Ordering::SeqCst) > before, "no write landed while the index was being created");
+    // and some more once it is ready
+    let after = writes.load(Ordering::SeqCst);
+    while writes.load(Ordering::SeqCst) < after + 100 {
+        tokio::task::yield_now().await;
+    }
+    stop.store(true, Ordering::SeqCst);
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    let documents = by_id(&db.scan(&table_name, json!({})).await.unwrap()["data"]);
+    let mut indexed = BTreeMap::new();
+    for group in 0..=GROUPS {
+        let output = db.query(&table_name, json!({"index_name": "by_g", "g": format!("g{}", group)})).await.unwrap();
+        for (id, document) in by_id(&output["data"]) {
+            assert_eq!(document["g"], format!("g{}", group));
+            assert!(indexed.insert(id, document).is_none());
+        }
+    }
+    let expected: BTreeMap<String, Value> = documents.into_iter().filter(|(_, document)| document.get("g").is_some()).collect();
+    assert_eq!(indexed, expected);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,208 @@
+use laws::config::{Config, CorruptionPolicy};
+use laws::database::Database;
+use laws::wal::SyncPolicy;
+use serde_json::{json, Value};
+
+// a fresh data directory per test, so tests can run in parallel
+fn data_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("laws-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn config(on_corruption: CorruptionPolicy) -> Config {
+    Config {
+        wal_sync: SyncPolicy::Always,
+        autosave_seconds: 0,
+        autosave_mutations: 0,
+        on_corruption,
+    }
+}
+
+async fn open(dir: &std::path::Path) -> Database {
+    Database::with_config(dir.to_str().unwrap(), config(CorruptionPolicy::Quarantine)).await.unwrap()
+}
+
+async fn create_table(db: &Database, table_name: &str) {
+    db.create_table(json!({
+        "table_name": table_name,
+        "primary_key": {"name": "id", "datatype": "String"},
+        "sort_key": {"name": "Null", "datatype": "Null"},
+    })).await.unwrap();
+}
+
+async fn put(db: &Database, table_name: &str, id: &str) {
+    db.create_document(&table_name.to_string(), json!({"id": id})).await.unwrap();
+}
+
+// the ids of every document in the table, sorted
+async fn ids(db: &Database, table_name: &str) -> Vec<String> {
+    let output = db.scan(&table_name.to_string(), json!({})).await.unwrap();
+    let mut ids: Vec<String> = output["data"].as_array().unwrap().iter()
+        .map(|document| document["id"].as_str().unwrap().to_string())
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn table_file(dir: &std::path::Path, file_name: &str) -> std::path::PathBuf {
+    dir.join("tables").join(file_name)
+}
+
+#[tokio::test]
+async fn reloads_unsaved_writes_from_the_log() {
+    let dir = data_dir("unsaved");
+    {
+        let db = open(&dir).await;
+        create_table(&db, "t").await;
+        put(&db, "t", "a").await;
+        put(&db, "t", "b").await;
+        db.delete_document(&String::from("t"), json!({"id": "a"})).await.unwrap();
+    }
+
+    let db = open(&dir).await;
+    assert_eq!(ids(&db, "t").await, vec!["b"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn recovers_a_corrupt_table_file_from_its_fallback_and_the_log() {
+    let dir = data_dir("fallback");
+    {
+        let db = open(&dir).await;
+        create_table(&db, "t").await;
+        put(&db, "t", "a").await;
+        db.save().await;
+        put(&db, "t", "b").await;
+        db.save().await;
+        put(&db, "t", "c").await;
+    }
+    std::fs::write(table_file(&dir, "t.json"), b"{\"lsn\": 3, \"tables\": [").unwrap();
+
+    let db = open(&dir).await;
+    assert_eq!(ids(&db, "t").await, vec!["a", "b", "c"]);
+    let quarantined = std::fs::read_dir(dir.join("tables")).unwrap().flatten()
+        .any(|entry| entry.file_name().to_string_lossy().starts_with("t.json.corrupt-"));
+    assert!(quarantined);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn refuses_to_start_on_a_corrupt_table_file() {
+    let dir = data_dir("refuse");
+    {
+        let db = open(&dir).await;
+        create_table(&db, "t").await;
+        put(&db, "t", "a").await;
+        db.save().await;
+    }
+    let corrupt = b"{\"lsn\": 2, \"tables\": [{\"table_name\": \"t\"}]}";
+    std::fs::write(table_file(&dir, "t.json"), corrupt).unwrap();
+
+    let refused = Database::with_config(dir.to_str().unwrap(), config(CorruptionPolicy::Refuse)).await.err();
+    assert!(matches!(refused, Some(laws::errors::DbError::StorageError(_))));
+    // nothing on disk is touched
+    assert_eq!(std::fs::read(table_file(&dir, "t.json")).unwrap(), corrupt);
+    assert!(table_file(&dir, "t.prev.json").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn rejects_a_table_file_with_documents_that_do_not_fit_its_keys() {
+    let dir = data_dir("unfitting");
+    {
+        let db = open(&dir).await;
+        create_table(&db, "t").await;
+        put(&db, "t", "a").await;
+        db.save().await;
+        put(&db, "t", "b").await;
+        db.save().await;
+    }
+    // a document without its key used to load the table empty, and the next save then wrote it that way
+    let file_path = table_file(&dir, "t.json");
+    let mut snapshot: Value = serde_json::from_slice(&std::fs::read(&file_path).unwrap()).unwrap();
+    snapshot["tables"][0]["data"].as_array_mut().unwrap().push(json!({"name": "no id"}));
+    std::fs::write(&file_path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+
+    let db = open(&dir).await;
+    assert_eq!(ids(&db, "t").await, vec!["a", "b"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn replays_a_transaction_whole_or_not_at_all() {
+    let dir = data_dir("transaction");
+    {
+        let db = open(&dir).await;
+        create_table(&db, "t").await;
+        create_table(&db, "u").await;
+        put(&db, "t", "a").await;
+        db.transact_write(json!({"items": [
+            {"put": {"table_name": "t", "document": {"id": "b"}}},
+            {"delete": {"table_name": "t", "key": {"id": "a"}}},
+            {"put": {"table_name": "u", "document": {"id": "c"}}},
+        ]})).await.unwrap();
+    }
+
+    // logged in full, the whole transaction comes back
+    {
+        let db = open(&dir).await;
+        assert_eq!(ids(&db, "t").await, vec!["b"]);
+        assert_eq!(ids(&db, "u").await, vec!["c"]);
+    }
+
+    // a crash partway through writing the record leaves none of it
+    let log_path = dir.join("wal.log");
+    let log = std::fs::read(&log_path).unwrap();
+    let start = log[..log.len() - 1].iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    assert!(log[start..].starts_with(b"{") && String::from_utf8_lossy(&log[start..]).contains("\"transact\""));
+    std::fs::write(&log_path, &log[..start + (log.len() - start) / 2]).unwrap();
+
+    let db = open(&dir).await;
+    assert_eq!(ids(&db, "t").await, vec!["a"]);
+    assert!(ids(&db, "u").await.is_empty());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn keeps_backups_in_a_file_of_their_own() {
+    let dir = data_dir("backups");
+    let restorable;
+    {
+        let db = open(&dir).await;
+        db.create_table(json!({
+            "table_name": "t",
+            "primary_key": {"name": "id", "datatype": "String"},
+            "sort_key": {"name": "Null", "datatype": "Null"},
+            "point_in_time_recovery": {},
+        })).await.unwrap();
+        put(&db, "t", "a").await;
+        db.save().await;
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        restorable = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        put(&db, "t", "b").await;
+        db.save().await;
+        put(&db, "t", "c").await;
+    }
+    // the table file leaves the backups out, their file gets a header and then a line per save's writes
+    assert!(!String::from_utf8(std::fs::read(table_file(&dir, "t.json")).unwrap()).unwrap().contains("\"backups\""));
+    let backups_path = table_file(&dir, "t.backups.log");
+    assert_eq!(std::fs::read_to_string(&backups_path).unwrap().lines().count(), 3);
+    // an append torn by a crash is left out
+    let mut contents = std::fs::read(&backups_path).unwrap();
+    contents.extend_from_slice(b"{\"lsn\": 9, \"timest");
+    std::fs::write(&backups_path, contents).unwrap();
+
+    let db = open(&dir).await;
+    db.restore_table(&String::from("t"), json!({"table_name": "before", "timestamp": restorable})).await.unwrap();
+    assert_eq!(ids(&db, "before").await, vec!["a"]);
+    db.restore_table(&String::from("t"), json!({"table_name": "now"})).await.unwrap();
+    assert_eq!(ids(&db, "now").await, vec!["a", "b", "c"]);
+    // and the next save starts the file over rather than appending after it
+    put(&db, "t", "d").await;
+    db.save().await;
+    let contents = std::fs::read_to_string(&backups_path).unwrap();
+    assert!(contents.lines().all(|line| serde_json::from_str::<Value>(line).is_ok()));
+    let _ = std::fs::remove_dir_all(&dir);
+}